@@ -0,0 +1,122 @@
+//! Free lists of every order, with the number of blocks in each
+
+use core::ops::Deref;
+use core::ptr;
+
+use crate::header::BlockHeader;
+use crate::ReusePolicy;
+
+/// Heads of the free lists of every order, and how many blocks each list holds
+///
+/// Lists are only modified through methods keeping the counts in sync,
+/// reading them goes through [`Deref`] to the heads.
+#[derive(Debug)]
+pub(crate) struct FreeLists<const ORDERS: usize> {
+    /// Sentinel header of the list of free blocks at each order
+    heads: [BlockHeader; ORDERS],
+    /// Number of free blocks in the list at each order
+    counts: [usize; ORDERS],
+    /// Last free block in the list at each order, null for an empty list so that it stays valid if the lists move
+    tails: [*mut BlockHeader; ORDERS],
+    /// End of the lists where free blocks are added
+    policy: ReusePolicy,
+}
+
+impl<const ORDERS: usize> FreeLists<ORDERS> {
+    /// Create empty free lists
+    pub(crate) const fn new() -> Self {
+        FreeLists {
+            heads: [BlockHeader::new(); ORDERS],
+            counts: [0; ORDERS],
+            tails: [ptr::null_mut(); ORDERS],
+            policy: ReusePolicy::Lifo,
+        }
+    }
+
+    /// Empty every list, keeping the policy
+    pub(crate) const fn clear(&mut self) {
+        self.heads = [BlockHeader::new(); ORDERS];
+        self.counts = [0; ORDERS];
+        self.tails = [ptr::null_mut(); ORDERS];
+    }
+
+    /// Set the end of the lists where free blocks are added
+    pub(crate) const fn set_policy(&mut self, policy: ReusePolicy) {
+        self.policy = policy;
+    }
+
+    /// Number of free blocks in the list at each order
+    #[inline]
+    pub(crate) const fn counts(&self) -> [usize; ORDERS] {
+        self.counts
+    }
+
+    /// Add a free block to the list of `order`
+    ///
+    /// # Safety
+    /// Same requirements as [`BlockHeader::push`].
+    pub(crate) unsafe fn push(&mut self, order: usize, node: *mut BlockHeader) {
+        let tail = self.tails[order];
+        match self.policy {
+            ReusePolicy::Lifo => self.heads[order].push(node),
+            // Appending after the last block, there is nothing to walk
+            ReusePolicy::Fifo if !tail.is_null() => (*tail).push_back(node),
+            ReusePolicy::Fifo => self.heads[order].push_back(node),
+        }
+        if tail.is_null() || self.policy == ReusePolicy::Fifo {
+            self.tails[order] = node;
+        }
+        self.counts[order] += 1;
+    }
+
+    /// Attempt to remove the first free block from the list of `order`
+    pub(crate) fn pop(&mut self, order: usize) -> Option<*mut BlockHeader> {
+        let node = self.heads[order].pop_next()?;
+        self.counts[order] -= 1;
+        if self.counts[order] == 0 {
+            self.tails[order] = ptr::null_mut();
+        }
+        Some(node)
+    }
+
+    /// Attempt to remove the free block at `addr` from the list of `order`, return `true` if it was found
+    pub(crate) fn remove(&mut self, order: usize, addr: usize) -> bool {
+        // Block before the one found, `None` for the first block
+        let mut prev = None;
+        let Some(node) = self.heads[order].iter_mut().skip(1).find(|&node| {
+            let found = node as usize == addr;
+            if !found {
+                prev = Some(node);
+            }
+            found
+        }) else {
+            return false;
+        };
+
+        // The first block links back to where the head was when it got pushed, which is stale if the allocator moved
+        if prev.is_none() {
+            self.heads[order].pop_next();
+        } else {
+            // SAFETY: the block was found in the list past the first one, so it links back to a valid header
+            unsafe { (*node).pop() };
+        }
+        if node == self.tails[order] {
+            self.tails[order] = prev.unwrap_or(ptr::null_mut());
+        }
+        self.counts[order] -= 1;
+        true
+    }
+
+    /// Return `true` if the free block at `addr` is in the list of `order`
+    pub(crate) fn contains(&self, order: usize, addr: usize) -> bool {
+        self.heads[order].iter().skip(1).any(|node| node as usize == addr)
+    }
+}
+
+impl<const ORDERS: usize> Deref for FreeLists<ORDERS> {
+    type Target = [BlockHeader; ORDERS];
+
+    fn deref(&self) -> &Self::Target {
+        &self.heads
+    }
+}
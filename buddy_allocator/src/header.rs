@@ -25,6 +25,8 @@ impl BlockHeader {
     /// # Safety
     /// `node` must not be null pointer and is properly aligned
     pub(crate) unsafe fn push(&mut self, node: *mut BlockHeader) {
+        debug_assert!(!node.is_null() && node.is_aligned(), "invalid block header {node:p}");
+
         (*node).next = self.next;
         (*node).prev = self;
 
@@ -34,6 +36,17 @@ impl BlockHeader {
         self.next = node;
     }
 
+    /// Add a node at the end of the list of header
+    ///
+    /// The list is walked up to its end, this is only constant time if called on the last header.
+    ///
+    /// # Safety
+    /// `node` must not be null pointer and is properly aligned
+    pub(crate) unsafe fn push_back(&mut self, node: *mut BlockHeader) {
+        let tail = self.iter_mut().last().unwrap_or(self);
+        (*tail).push(node);
+    }
+
     /// Attempt to remove the next header from the list
     pub(crate) fn pop_next(&mut self) -> Option<*mut BlockHeader> {
         if self.is_tail() {
@@ -68,15 +81,31 @@ impl BlockHeader {
 
     /// Return `true` if the list ended with this header
     #[inline]
-    pub(crate) fn is_tail(&self) -> bool {
+    pub(crate) const fn is_tail(&self) -> bool {
         self.next.is_null()
     }
 
     /// Return an mutable iterator over the headers in the list
     #[inline]
-    pub(crate) fn iter_mut(&mut self) -> Iter {
+    pub(crate) const fn iter_mut(&mut self) -> Iter {
         Iter { node: self }
     }
+
+    /// Return an iterator over the headers in the list
+    #[inline]
+    pub(crate) const fn iter(&self) -> IterShared {
+        IterShared { node: self }
+    }
+
+    /// Return an iterator over the headers in the list, from the last one back to this header
+    pub(crate) fn iter_rev(&self) -> IterSharedRev {
+        let end: *const BlockHeader = self;
+        IterSharedRev {
+            node: self.iter().last().unwrap_or(end),
+            first: self.next,
+            end,
+        }
+    }
 }
 
 /// An iterator over the linked list
@@ -100,6 +129,59 @@ impl Iterator for Iter {
     }
 }
 
+/// A read-only iterator over the linked list
+pub(crate) struct IterShared {
+    /// Current header
+    node: *const BlockHeader,
+}
+impl Iterator for IterShared {
+    type Item = *const BlockHeader;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node.is_null() {
+            None
+        } else {
+            let node = self.node;
+            let next = unsafe { (*self.node).next };
+            self.node = next;
+
+            Some(node)
+        }
+    }
+}
+
+/// A read-only iterator over the linked list, following `prev` pointers
+///
+/// The first header after `end` is not trusted to link back to it, as `end` may have moved since it was pushed.
+pub(crate) struct IterSharedRev {
+    /// Current header
+    node: *const BlockHeader,
+    /// Header following `end` in the list
+    first: *const BlockHeader,
+    /// Header the iteration stops after
+    end: *const BlockHeader,
+}
+impl Iterator for IterSharedRev {
+    type Item = *const BlockHeader;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node.is_null() {
+            None
+        } else {
+            let node = self.node;
+            self.node = if ptr::eq(node, self.end) {
+                ptr::null()
+            } else if ptr::eq(node, self.first) {
+                self.end
+            } else {
+                unsafe { (*node).prev }
+            };
+
+            Some(node)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,7 +222,7 @@ mod tests {
 
         /* `main_node` -> `node_2` */
         let popped = main_node.pop_next();
-        assert!(popped.is_some_and(|ptr| ptr == &mut node_1 as *mut _));
+        assert!(popped.is_some_and(|ptr| ptr::eq(ptr, &node_1)));
         assert!(main_node.prev.is_null());
         assert_eq!(main_node.next, &mut node_2 as *mut _);
         assert_eq!(node_2.prev, &mut main_node as *mut _);
@@ -150,7 +232,7 @@ mod tests {
 
         /* `main_node` */
         let popped = main_node.pop_next();
-        assert!(popped.is_some_and(|ptr| ptr == &mut node_2 as *mut _));
+        assert!(popped.is_some_and(|ptr| ptr::eq(ptr, &node_2)));
         assert!(main_node.prev.is_null());
         assert!(main_node.next.is_null());
         assert!(node_1.prev.is_null());
@@ -200,6 +282,42 @@ mod tests {
         assert!(node_2.next.is_null());
     }
 
+    #[test]
+    fn test_push_back() {
+        let mut main_node = BlockHeader::new();
+        let mut nodes = [BlockHeader::new(); 3];
+        let [node_0, node_1, node_2] = nodes.each_mut().map(|node| node as *mut BlockHeader);
+
+        /* `main_node` -> `node_0` -> `node_1` -> `node_2` */
+        unsafe { main_node.push_back(node_0) };
+        unsafe { main_node.push_back(node_1) };
+        unsafe { main_node.push_back(node_2) };
+        assert_eq!(main_node.pop_next(), Some(node_0));
+        assert_eq!(main_node.pop_next(), Some(node_1));
+        assert_eq!(main_node.pop_next(), Some(node_2));
+        assert!(main_node.pop_next().is_none());
+
+        /* `main_node` -> `node_2` -> `node_1` -> `node_0` */
+        unsafe { main_node.push(node_0) };
+        unsafe { main_node.push(node_1) };
+        unsafe { main_node.push(node_2) };
+        assert_eq!(main_node.pop_next(), Some(node_2));
+        assert_eq!(main_node.pop_next(), Some(node_1));
+        assert_eq!(main_node.pop_next(), Some(node_0));
+        assert!(main_node.pop_next().is_none());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invalid block header")]
+    fn test_push_misaligned() {
+        let mut main_node = BlockHeader::new();
+        let mut nodes = [BlockHeader::new(); 2];
+
+        let misaligned = (nodes.as_mut_ptr() as *mut u8).wrapping_add(1) as *mut BlockHeader;
+        unsafe { main_node.push(misaligned) };
+    }
+
     #[test]
     fn test_iter() {
         let mut main_node = BlockHeader::new();
@@ -235,4 +353,71 @@ mod tests {
         assert!(node_2.prev.is_null());
         assert!(node_2.next.is_null());
     }
+
+    #[test]
+    fn test_iter_shared() {
+        let mut main_node = BlockHeader::new();
+        let mut node_1 = BlockHeader::new();
+        let mut node_2 = BlockHeader::new();
+
+        /* `main_node` -> `node_1` -> `node_2` */
+        unsafe { main_node.push(&mut node_2) };
+        unsafe { main_node.push(&mut node_1) };
+
+        let mut iterator = main_node.iter();
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &main_node)));
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &node_1)));
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &node_2)));
+        assert!(iterator.next().is_none());
+
+        // Nothing is modified by traversing
+        assert_eq!(main_node.iter().count(), 3);
+        assert_eq!(main_node.iter_rev().count(), 3);
+        assert_eq!(main_node.next, &mut node_1 as *mut _);
+        assert_eq!(node_1.next, &mut node_2 as *mut _);
+    }
+
+    #[test]
+    #[allow(clippy::shadow_unrelated)]
+    fn test_iter_rev() {
+        let mut main_node = BlockHeader::new();
+        let mut node_1 = BlockHeader::new();
+        let mut node_2 = BlockHeader::new();
+        let mut node_3 = BlockHeader::new();
+
+        /* `main_node` -> `node_1` -> `node_2` -> `node_3` */
+        unsafe { main_node.push(&mut node_3) };
+        unsafe { main_node.push(&mut node_2) };
+        unsafe { main_node.push(&mut node_1) };
+
+        let mut forward = [ptr::null(); 4];
+        let mut backward = [ptr::null(); 4];
+        forward
+            .iter_mut()
+            .zip(main_node.iter())
+            .for_each(|(slot, node)| *slot = node);
+        backward
+            .iter_mut()
+            .zip(main_node.iter_rev())
+            .for_each(|(slot, node)| *slot = node);
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        let mut iterator = main_node.iter_rev();
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &node_3)));
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &node_2)));
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &node_1)));
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &main_node)));
+        assert!(iterator.next().is_none());
+
+        // Stops at the header it started from
+        let mut iterator = node_2.iter_rev();
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &node_3)));
+        assert!(iterator.next().is_some_and(|ptr| ptr::eq(ptr, &node_2)));
+        assert!(iterator.next().is_none());
+
+        // A lone header
+        node_1.pop();
+        assert_eq!(node_1.iter_rev().count(), 1);
+    }
 }
@@ -1,19 +1,23 @@
 //! A naive implementation of the buddy memory allocator
 
 #![no_std]
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
 
 use core::alloc::Layout;
 use core::{
     marker::PhantomData,
     mem::size_of,
-    ptr::{null_mut, NonNull},
-    slice::from_raw_parts_mut,
+    ptr::{slice_from_raw_parts_mut, without_provenance_mut, NonNull},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 use spin::Mutex;
 
 mod header;
 use header::BlockHeader;
 
+mod free_lists;
+use free_lists::FreeLists;
+
 #[cfg(test)]
 mod tests;
 
@@ -24,10 +28,189 @@ const MIN_BLOCK_SIZE: usize = size_of::<BlockHeader>();
 /// Order of the minimal block size allocatable
 const BASE_ORDER: usize = MIN_BLOCK_SIZE.trailing_zeros() as usize;
 
+/// Maximum number of distinct tags that can have live allocations at the same time
+pub const MAX_TAGS: usize = 8;
+/// Maximum number of non-adjacent memory pools whose ranges an allocator records, see [`BuddyAllocator::add_memory`]
+pub const MAX_POOLS: usize = 16;
+
 /// Get order of an allocator for a max block size
+///
+/// Return `None` if `max_block_size` is not a power of two at least as large as the minimal block size.
+#[inline(always)]
+pub const fn order_from_max_block_size(max_block_size: usize) -> Option<usize> {
+    if !max_block_size.is_power_of_two() || max_block_size < MIN_BLOCK_SIZE {
+        return None;
+    }
+
+    Some(max_block_size.trailing_zeros() as usize - BASE_ORDER + 1)
+}
+
+/// Get max block size of an allocator for an order, inverse of [`order_from_max_block_size`]
+///
+/// `orders` must be at least 1, like the `ORDERS` of an allocator, there is no max block size without any order.
+#[inline(always)]
+pub const fn max_block_size_from_order(orders: usize) -> usize {
+    1 << (orders + BASE_ORDER - 1)
+}
+
+/// Get the order of an allocator whose max block size is the largest block fitting in a region of `region_size`
+///
+/// With it, an aligned region whose size is a power of two is added as a single block.
+/// Return `None` if the region is too small to hold any block.
+#[inline(always)]
+pub const fn recommended_orders(region_size: usize) -> Option<usize> {
+    if region_size < MIN_BLOCK_SIZE {
+        return None;
+    }
+
+    Some(region_size.ilog2() as usize - BASE_ORDER + 1)
+}
+
+/// Size of the block that is handed out for a `layout`
 #[inline(always)]
-pub const fn order_from_max_block_size(max_block_size: usize) -> usize {
-    max_block_size.trailing_zeros() as usize - BASE_ORDER + 1
+fn block_size(layout: Layout) -> usize {
+    MIN_BLOCK_SIZE
+        .max(layout.size().next_power_of_two())
+        .max(layout.align())
+}
+
+/// Dangling but aligned pointer handed out for a zero-sized `layout`
+#[inline(always)]
+const fn dangling(layout: Layout) -> NonNull<[u8]> {
+    // SAFETY: alignment is never zero
+    let ptr = unsafe { NonNull::new_unchecked(without_provenance_mut(layout.align())) };
+    NonNull::slice_from_raw_parts(ptr, 0)
+}
+
+/* -------------------------------------------------------------------------------- */
+
+/// Reason an allocation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocFailure {
+    /// No free block is large enough for the request
+    OutOfMemory,
+    /// Requested alignment is larger than the maximum block size
+    AlignmentTooLarge,
+    /// Requested size is larger than the maximum block size
+    SizeTooLarge,
+    /// `MAX_TAGS` other tags already have live allocations, see [`BuddyAllocator::try_allocate_tagged`]
+    TooManyTags,
+}
+
+/// Broken invariant of the heap, found by [`BuddyAllocator::check_invariants`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// The number of blocks in the free list of `order` differs from its recorded count
+    CountMismatch {
+        /// Order of the list
+        order: usize,
+    },
+    /// The free block at `addr` is not aligned to the size of its order
+    Misaligned {
+        /// Address of the block
+        addr: usize,
+        /// Order of the block
+        order: usize,
+    },
+    /// The free block at `addr` is not entirely within a pool added to the heap
+    OutsidePools {
+        /// Address of the block
+        addr: usize,
+        /// Order of the block
+        order: usize,
+    },
+    /// The free block at `addr` overlaps another free block, possibly itself in another list
+    Overlap {
+        /// Address of the block
+        addr: usize,
+        /// Order of the block
+        order: usize,
+    },
+    /// The free block at `addr` and its buddy are both free but were not merged
+    Uncoalesced {
+        /// Address of the block
+        addr: usize,
+        /// Order of the block
+        order: usize,
+    },
+    /// Some pools were added past `MAX_POOLS` and their ranges are not recorded, so blocks cannot be checked
+    UntrackedPools,
+}
+
+/// Error of [`BuddyAllocator::clear`], some pools were added past `MAX_POOLS` and their ranges are not recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UntrackedPools;
+
+/// Which free block of an order is handed out first, see [`BuddyAllocator::set_reuse_policy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReusePolicy {
+    /// The most recently freed block
+    #[default]
+    Lifo,
+    /// The least recently freed block, the last block of each order is tracked to append in constant time
+    Fifo,
+}
+
+/// Allocation statistics of an allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocStats {
+    /// Bytes currently allocated
+    pub used: usize,
+    /// Highest number of bytes allocated at the same time
+    pub peak: usize,
+    /// Number of live allocations
+    pub allocations: usize,
+}
+
+/// Outcome of adding a memory pool with [`BuddyAllocator::add_memory_detailed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AddReport {
+    /// Bytes added to the heap
+    pub added: usize,
+    /// Bytes skipped at the start of the pool to align it
+    pub wasted_head: usize,
+    /// Bytes skipped at the end of the pool to align it
+    pub wasted_tail: usize,
+}
+
+/* -------------------------------------------------------------------------------- */
+
+/// Live memory accounted to an allocation tag
+#[derive(Debug, Clone, Copy)]
+struct TagUsage {
+    /// Tag of the subsystem using the memory
+    tag: u16,
+    /// Bytes currently allocated under `tag`, an unused slot has none
+    bytes: usize,
+}
+
+impl TagUsage {
+    /// Create an unused slot
+    const fn new() -> Self {
+        TagUsage { tag: 0, bytes: 0 }
+    }
+}
+
+/// Address range of memory added to the heap
+#[derive(Debug, Clone, Copy)]
+struct Pool {
+    /// Start address of the range
+    start: usize,
+    /// End address of the range, an unused slot has the same start and end
+    end: usize,
+}
+
+impl Pool {
+    /// Create an unused slot
+    const fn new() -> Self {
+        Pool { start: 0, end: 0 }
+    }
+
+    /// Return `true` if the slot does not hold a range
+    #[inline]
+    const fn is_unused(&self) -> bool {
+        self.start == self.end
+    }
 }
 
 /* -------------------------------------------------------------------------------- */
@@ -50,15 +233,43 @@ pub const fn order_from_max_block_size(max_block_size: usize) -> usize {
 /// ```
 #[derive(Debug)]
 pub struct BuddyAllocator<'a, const ORDERS: usize> {
-    /// List of pointers to the first free block at each level
-    free_list: Mutex<[BlockHeader; ORDERS]>,
+    /// List of pointers to the first free block at each level, locked before any other lock
+    free_list: Mutex<FreeLists<ORDERS>>,
+    /// Per-tag usage of memory allocated with [`BuddyAllocator::get_memory_tagged`], locked after any other lock
+    tags: Mutex<[TagUsage; MAX_TAGS]>,
+    /// Memory ranges added to the heap, locked after `free_list` and before `tags`
+    pools: Mutex<[Pool; MAX_POOLS]>,
+    /// Set once a pool was added without room in `pools` to record it, only updated while `pools` is locked
+    untracked_pools: AtomicBool,
+    /// No order above this one has a free block, only updated while `free_list` is locked
+    highest_nonempty_order: AtomicUsize,
+    /// Bytes added to the heap, only updated while `pools` is locked
+    capacity: AtomicUsize,
+    /// Bytes currently allocated, only updated while `free_list` is locked
+    used_bytes: AtomicUsize,
+    /// Highest value of `used_bytes`, only updated while `free_list` is locked
+    peak_bytes: AtomicUsize,
+    /// Number of live allocations, only updated while `free_list` is locked
+    allocations: AtomicUsize,
+    /// Callback invoked when an allocation through [`GlobalAlloc`] fails
+    #[cfg(feature = "global-alloc")]
+    alloc_error_hook: Mutex<Option<fn(Layout)>>,
     /// Phantom data, keeping memory pools added to this allocator valid
     _pd: PhantomData<&'a [u8]>,
 }
 
 impl<'a, const ORDERS: usize> BuddyAllocator<'a, ORDERS> {
     /// Maximum block size allocatable, accessible with type
-    pub const MAX_BLOCK_SIZE: usize = 1 << (ORDERS + BASE_ORDER - 1);
+    pub const MAX_BLOCK_SIZE: usize = max_block_size_from_order(ORDERS);
+
+    /// Compile-time check that there is at least one order and that `MAX_BLOCK_SIZE` does not overflow a `usize`
+    const ORDERS_FIT: () = {
+        assert!(ORDERS >= 1, "`ORDERS` must be at least 1, no block could be allocated");
+        assert!(
+            ORDERS + BASE_ORDER - 1 < usize::BITS as usize,
+            "`ORDERS` is too large, `MAX_BLOCK_SIZE` would overflow"
+        );
+    };
 
     /// Maximum block size allocatable, accessible with instance
     #[inline(always)]
@@ -66,28 +277,209 @@ impl<'a, const ORDERS: usize> BuddyAllocator<'a, ORDERS> {
         Self::MAX_BLOCK_SIZE
     }
 
+    /// Largest size a single allocation can request, requests above it always fail
+    #[inline(always)]
+    pub const fn max_alloc(&self) -> usize {
+        Self::MAX_BLOCK_SIZE
+    }
+
     /// Create an allocator with no memory yet
+    ///
+    /// `ORDERS` is bounded so that `MAX_BLOCK_SIZE` fits in a `usize`, larger values fail to compile:
+    /// ```compile_fail
+    /// let allocator = buddy_allocator::BuddyAllocator::<{ usize::BITS as usize }>::new();
+    /// ```
+    /// An allocator needs at least one order, 0 fails to compile as well:
+    /// ```compile_fail
+    /// let allocator = buddy_allocator::BuddyAllocator::<0>::new();
+    /// ```
     pub const fn new() -> Self {
+        let () = Self::ORDERS_FIT;
+
         BuddyAllocator {
-            free_list: Mutex::new([BlockHeader::new(); ORDERS]),
+            free_list: Mutex::new(FreeLists::new()),
+            tags: Mutex::new([TagUsage::new(); MAX_TAGS]),
+            pools: Mutex::new([Pool::new(); MAX_POOLS]),
+            untracked_pools: AtomicBool::new(false),
+            highest_nonempty_order: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(0),
+            used_bytes: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            #[cfg(feature = "global-alloc")]
+            alloc_error_hook: Mutex::new(None),
             _pd: PhantomData,
         }
     }
 
+    /// Create an allocator with a memory pool already added to its heap
+    ///
+    /// # Safety
+    /// Same requirements as [`BuddyAllocator::add_memory`].
+    pub unsafe fn with_region(pool_addr: *mut u8, pool_size: usize) -> Self {
+        let allocator = Self::new();
+        allocator.add_memory(pool_addr, pool_size);
+        allocator
+    }
+
     /// Add a memory pool to the heap of this allocator
     ///
+    /// Blocks are never larger than `MAX_BLOCK_SIZE`, so a large pool is split in many top blocks if `ORDERS` is small,
+    /// see [`recommended_orders`] to choose it.
+    /// A null `pool_addr` or a zero `pool_size` is a no-op.
+    ///
+    /// The range of each pool is recorded, a pool adjacent to a recorded one extends it. Past `MAX_POOLS` ranges
+    /// not adjacent to each other, memory is still added but its range is not recorded anymore:
+    /// [`BuddyAllocator::clear`] and [`BuddyAllocator::check_invariants`] then refuse to run, as they need every range.
+    ///
     /// # Safety
     /// The caller must ensure that there is no reference that
     /// point to the contents of the `UnsafeCell`.
     pub unsafe fn add_memory(&self, pool_addr: *mut u8, pool_size: usize) -> usize {
-        let mut start = pool_addr as usize;
-        let mut end = start + pool_size;
+        self.add_memory_detailed(pool_addr, pool_size).added
+    }
+
+    /// Add a memory pool to the heap like [`BuddyAllocator::add_memory`], also reporting the bytes lost to alignment
+    ///
+    /// A null or empty pool is reported with nothing added nor wasted.
+    ///
+    /// # Safety
+    /// Same requirements as [`BuddyAllocator::add_memory`].
+    pub unsafe fn add_memory_detailed(&self, pool_addr: *mut u8, pool_size: usize) -> AddReport {
+        if pool_addr.is_null() || pool_size == 0 {
+            return AddReport::default();
+        }
+        let addr = pool_addr as usize;
 
         // Ensure alignment
-        start = (start + MIN_BLOCK_SIZE - 1) & (!MIN_BLOCK_SIZE + 1);
-        end &= !MIN_BLOCK_SIZE + 1;
+        let start = (addr + MIN_BLOCK_SIZE - 1) & (!MIN_BLOCK_SIZE + 1);
+        let end = (addr + pool_size) & (!MIN_BLOCK_SIZE + 1);
+        if start >= end {
+            // Too small to hold a single block once aligned
+            let wasted_head = pool_size.min(start - addr);
+            return AddReport {
+                added: 0,
+                wasted_head,
+                wasted_tail: pool_size - wasted_head,
+            };
+        }
 
         let mut free_list = self.free_list.lock();
+        let mut pools = self.pools.lock();
+        // Extend the range of the adjacent pools, fusing them if this one fills the gap between them,
+        // or record a new one
+        let before = pools.iter().position(|pool| !pool.is_unused() && pool.end == start);
+        let after = pools.iter().position(|pool| !pool.is_unused() && pool.start == end);
+        let fused = Pool {
+            start: before.map_or(start, |before| pools[before].start),
+            end: after.map_or(end, |after| pools[after].end),
+        };
+        if let (Some(_), Some(after)) = (before, after) {
+            pools[after] = Pool::new();
+        }
+        match before.or(after).or_else(|| pools.iter().position(Pool::is_unused)) {
+            Some(slot) => pools[slot] = fused,
+            // The memory is still usable, only operations needing every range are lost
+            None => self.untracked_pools.store(true, Ordering::Relaxed),
+        }
+
+        let added = self.add_range(&mut free_list, start, end);
+        self.capacity.fetch_add(added, Ordering::Relaxed);
+        AddReport {
+            added,
+            wasted_head: start - addr,
+            wasted_tail: addr + pool_size - end,
+        }
+    }
+
+    /// Add a memory pool to the heap of this allocator like [`BuddyAllocator::add_memory`], from a borrowed buffer
+    ///
+    /// The buffer stays exclusively borrowed for as long as the allocator lives, so nothing else can alias it.
+    pub fn add_memory_slice(&self, pool: &'a mut [u8]) -> usize {
+        // SAFETY: the exclusive borrow is held for `'a`, no other reference to the pool can exist
+        unsafe { self.add_memory(pool.as_mut_ptr(), pool.len()) }
+    }
+
+    /// Reset the heap to its initial state, with every added pool entirely free
+    ///
+    /// # Errors
+    /// Return [`UntrackedPools`] and leave the heap untouched if some pools were added past `MAX_POOLS`,
+    /// see [`BuddyAllocator::add_memory`].
+    ///
+    /// # Safety
+    /// All memory allocated so far is given back to the heap,
+    /// the caller must ensure that none of it is used anymore.
+    pub unsafe fn clear(&self) -> Result<(), UntrackedPools> {
+        {
+            let mut free_list = self.free_list.lock();
+            let pools = self.pools.lock();
+            if self.untracked_pools.load(Ordering::Relaxed) {
+                return Err(UntrackedPools);
+            }
+
+            free_list.clear();
+            self.highest_nonempty_order.store(0, Ordering::Relaxed);
+            self.used_bytes.store(0, Ordering::Relaxed);
+            self.allocations.store(0, Ordering::Relaxed);
+            for pool in pools.iter() {
+                self.add_range(&mut free_list, pool.start, pool.end);
+            }
+        }
+
+        // The heap is released first, nothing needs to be held while resetting the tags
+        *self.tags.lock() = [TagUsage::new(); MAX_TAGS];
+        Ok(())
+    }
+
+    /// Merge every pair of free buddies in the heap, until no more merging is possible
+    ///
+    /// Blocks are already merged as they are added or given back, so this is a no-op for a heap built through the
+    /// public API. It is only a safety net restoring a fully coalesced heap if buddies were ever left apart.
+    pub fn merge_pools(&self) {
+        self.merge_buddies(&mut self.free_list.lock(), usize::MAX);
+    }
+
+    /// Merge up to `max_merges` pairs of free buddies in the heap, return how many were merged
+    ///
+    /// Works like [`BuddyAllocator::merge_pools`] with a bounded amount of work, so coalescing can be spread over
+    /// several calls. Returns 0 once no more merging is possible, which is always the case for a heap built through
+    /// the public API.
+    pub fn defragment(&self, max_merges: usize) -> usize {
+        self.merge_buddies(&mut self.free_list.lock(), max_merges)
+    }
+
+    /// Merge up to `max_merges` pairs of free buddies, smallest orders first, return how many were merged
+    fn merge_buddies(&self, free_list: &mut FreeLists<ORDERS>, max_merges: usize) -> usize {
+        let mut merges = 0;
+        // A merged block only goes up an order, so one pass from the bottom reaches the fixed point
+        for index in 0..ORDERS.saturating_sub(1) {
+            let buddy_of = |block: usize| block ^ (MIN_BLOCK_SIZE << index);
+            while merges < max_merges {
+                let Some(block) = free_list[index]
+                    .iter()
+                    .skip(1)
+                    .map(|node| node as usize)
+                    .find(|&block| free_list.contains(index, buddy_of(block)))
+                else {
+                    break;
+                };
+                free_list.remove(index, block);
+                free_list.remove(index, buddy_of(block));
+                // SAFETY: both halves are free, together they form a free block aligned to its size
+                unsafe { free_list.push(index + 1, block.min(buddy_of(block)) as *mut _) };
+                self.highest_nonempty_order.fetch_max(index + 1, Ordering::Relaxed);
+                merges += 1;
+            }
+        }
+
+        merges
+    }
+
+    /// Split a memory range into blocks and put them into the free lists, return the size added
+    ///
+    /// # Safety
+    /// `start` and `end` must be aligned to `MIN_BLOCK_SIZE`, the memory between them must be unused.
+    unsafe fn add_range(&self, free_list: &mut FreeLists<ORDERS>, mut start: usize, end: usize) -> usize {
         let mut added = 0;
         while start + MIN_BLOCK_SIZE <= end {
             // Block must be properly align before accommodating largest possible block that the allocator support
@@ -96,7 +488,8 @@ impl<'a, const ORDERS: usize> BuddyAllocator<'a, ORDERS> {
                 .min((end - start + 1).next_power_of_two() >> 1); // Maximum block size fits in remaining memory
             let order = size.trailing_zeros() as usize - BASE_ORDER;
 
-            free_list[order].push(start as *mut _);
+            // Blocks from an adjacent pool added earlier may be buddies of this one
+            self.insert_block(free_list, start, order);
             added += size;
             start += size;
         }
@@ -105,74 +498,534 @@ impl<'a, const ORDERS: usize> BuddyAllocator<'a, ORDERS> {
     }
 
     /// Allocate a piece of memory from the pool, satisfying `layout` requirements
+    ///
+    /// The returned slice spans the whole block, the header kept in a block while it is free is not reserved,
+    /// so a `MAX_BLOCK_SIZE` request yields exactly `MAX_BLOCK_SIZE` usable bytes.
+    ///
+    /// A zero-sized `layout` gets a dangling but aligned pointer of length 0, no block is used for it.
+    /// A larger block is only split once it is taken, so a failed allocation leaves the heap untouched.
     /// # Safety
     pub unsafe fn get_memory(&self, layout: Layout) -> Option<NonNull<[u8]>> {
-        let size = MIN_BLOCK_SIZE
-            .max(layout.size().next_power_of_two())
-            .max(layout.align());
+        self.try_allocate(layout).ok()
+    }
+
+    /// Allocate a piece of memory like [`BuddyAllocator::get_memory`], telling why on failure
+    ///
+    /// # Errors
+    /// Return the [`AllocFailure`] preventing the allocation.
+    /// # Safety
+    pub unsafe fn try_allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocFailure> {
+        if layout.size() == 0 {
+            return Ok(dangling(layout));
+        }
+        if layout.align() > Self::MAX_BLOCK_SIZE {
+            return Err(AllocFailure::AlignmentTooLarge);
+        }
+        if layout.size() > Self::MAX_BLOCK_SIZE {
+            return Err(AllocFailure::SizeTooLarge);
+        }
+
+        self.get_memory_with_order(layout)
+            .map(|(block, _)| block)
+            .ok_or(AllocFailure::OutOfMemory)
+    }
+
+    /// Allocate a piece of memory like [`BuddyAllocator::get_memory`], also returning the order of the granted block
+    ///
+    /// A block of order `n` is `MIN_BLOCK_SIZE << n` bytes long.
+    /// A zero-sized `layout` has no block to grant, so `None` is returned for it, unlike [`BuddyAllocator::get_memory`].
+    /// # Safety
+    pub unsafe fn get_memory_with_order(&self, layout: Layout) -> Option<(NonNull<[u8]>, usize)> {
+        if layout.size() == 0 {
+            return None;
+        }
+        let size = block_size(layout);
+        // Would be an order beyond the free lists
+        if size > Self::MAX_BLOCK_SIZE {
+            return None;
+        }
         let index = size.trailing_zeros() as usize - BASE_ORDER;
 
         let mut free_list = self.free_list.lock();
-        for i in index..ORDERS {
-            // Find smallest order that is available for allocation
-            if free_list[i].is_tail() {
-                continue;
-            }
+        self.take_block(&mut free_list, index).map(|block| (block, index))
+    }
 
-            // Split the block if it is larger than requested, until a block of requested size is available
-            for j in (index + 1..i + 1).rev() {
-                if let Some(block) = free_list[j].pop_next() {
-                    let block_size = 1 << (j + BASE_ORDER - 1);
-                    // SAFETY: pointer is within the larger block
-                    let buddy = (block as *mut u8).add(block_size) as *mut BlockHeader;
+    /// Allocate pieces of memory satisfying the same `layout` into every slot of `out`, locking the heap only once
+    ///
+    /// Slots that could not be satisfied are set to `None`.
+    /// # Safety
+    pub unsafe fn get_memory_many(&self, layout: Layout, out: &mut [Option<NonNull<[u8]>>]) {
+        if layout.size() == 0 {
+            out.fill(Some(dangling(layout)));
+            return;
+        }
 
-                    // SAFETY: pointer is within the larger block, its size does not overflow
-                    free_list[j - 1].push(buddy);
-                    free_list[j - 1].push(block);
-                }
-            }
+        let size = block_size(layout);
+        if size > Self::MAX_BLOCK_SIZE {
+            out.fill(None);
+            return;
+        }
 
-            break;
+        let index = size.trailing_zeros() as usize - BASE_ORDER;
+        let mut free_list = self.free_list.lock();
+        for slot in out.iter_mut() {
+            *slot = self.take_block(&mut free_list, index);
         }
+    }
 
-        free_list[index]
-            .pop_next()
-            .and_then(|ptr| NonNull::new(from_raw_parts_mut(ptr as *mut _, size)))
+    /// Allocate up to `count` blocks of order `order` into the first slots of `out`, locking the heap only once
+    ///
+    /// A block of order `n` is `MIN_BLOCK_SIZE << n` bytes long and aligned to its size.
+    /// Slots that could not be satisfied are set to `None`, return the number of blocks granted.
+    /// # Safety
+    /// Each block must be given back with a layout of the size of the block, e.g. `Layout::from_size_align(size, size)`.
+    pub unsafe fn reserve_blocks(&self, order: usize, count: usize, out: &mut [Option<NonNull<u8>>]) -> usize {
+        let slots = out.iter_mut().take(count);
+        if order >= ORDERS {
+            slots.for_each(|slot| *slot = None);
+            return 0;
+        }
+
+        let mut free_list = self.free_list.lock();
+        let mut granted = 0;
+        for slot in slots {
+            *slot = self.take_block(&mut free_list, order).map(NonNull::cast);
+            granted += usize::from(slot.is_some());
+        }
+
+        granted
     }
 
     /// Deallocate a piece of memory
+    ///
+    /// Nothing is done for a zero-sized `layout`, as no block was used for it.
     /// # Safety
     pub unsafe fn return_memory(&self, ptr: NonNull<u8>, layout: Layout) {
-        let size = MIN_BLOCK_SIZE
-            .max(layout.size().next_power_of_two())
-            .max(layout.align());
-        let mut index = size.trailing_zeros() as usize - BASE_ORDER;
+        if layout.size() == 0 {
+            return;
+        }
+
+        let size = block_size(layout);
+        let index = size.trailing_zeros() as usize - BASE_ORDER;
 
         let mut free_list = self.free_list.lock();
-        let mut block = ptr.as_ptr() as usize;
-        for list in free_list.iter_mut().rev().skip(1).rev().skip(index) {
-            let buddy = block ^ (1 << (index + BASE_ORDER));
-            let mut has_buddy = false;
+        self.insert_block(&mut free_list, ptr.as_ptr() as usize, index);
+        self.record_deallocation(size);
+    }
 
-            for node in list.iter_mut().skip(1) {
-                if node as usize != buddy {
-                    continue;
+    /// Attempt to grow a piece of memory to fit `new_layout` without moving it
+    ///
+    /// It succeeds if the current block already fits `new_layout`,
+    /// or if the block is the start of a larger block whose other parts are all free.
+    /// # Safety
+    /// `ptr` must have been allocated from this allocator with `old_layout`,
+    /// and `new_layout` must not be smaller than `old_layout`.
+    pub unsafe fn grow_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<[u8]>> {
+        // No block was used for a zero-sized allocation
+        if old_layout.size() == 0 {
+            return None;
+        }
+
+        let old_size = block_size(old_layout);
+        let new_size = block_size(new_layout);
+        let addr = ptr.as_ptr() as usize;
+        if new_size <= old_size {
+            return Some(NonNull::slice_from_raw_parts(ptr, old_size));
+        }
+        // The block must be the start of a larger one, and that one must exist
+        if new_size > Self::MAX_BLOCK_SIZE || addr & (new_size - 1) != 0 {
+            return None;
+        }
+
+        let old_index = old_size.trailing_zeros() as usize - BASE_ORDER;
+        let new_index = new_size.trailing_zeros() as usize - BASE_ORDER;
+
+        let mut free_list = self.free_list.lock();
+        if !(old_index..new_index).all(|index| free_list.contains(index, addr + (MIN_BLOCK_SIZE << index))) {
+            return None;
+        }
+
+        for index in old_index..new_index {
+            free_list.remove(index, addr + (MIN_BLOCK_SIZE << index));
+        }
+        self.refresh_highest_nonempty_order(&free_list);
+        self.record_resize(old_size, new_size);
+
+        Some(NonNull::slice_from_raw_parts(ptr, new_size))
+    }
+
+    /// Attempt to shrink a piece of memory to fit `new_layout` without moving it, giving back the unused part
+    ///
+    /// It fails only if `new_layout` is aligned more strictly than the current block.
+    /// # Safety
+    /// `ptr` must have been allocated from this allocator with `old_layout`,
+    /// and `new_layout` must not be larger than `old_layout`.
+    pub unsafe fn shrink_in_place(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<[u8]>> {
+        if new_layout.size() == 0 {
+            self.return_memory(ptr, old_layout);
+            return Some(dangling(new_layout));
+        }
+
+        let old_size = block_size(old_layout);
+        let new_size = block_size(new_layout);
+        if new_size > old_size {
+            return None;
+        }
+
+        let old_index = old_size.trailing_zeros() as usize - BASE_ORDER;
+        let new_index = new_size.trailing_zeros() as usize - BASE_ORDER;
+
+        // Upper halves of the block down to the new order are free, their buddies are still in use
+        let mut free_list = self.free_list.lock();
+        for index in new_index..old_index {
+            self.insert_block(&mut free_list, ptr.as_ptr() as usize + (MIN_BLOCK_SIZE << index), index);
+        }
+        self.record_resize(old_size, new_size);
+
+        Some(NonNull::slice_from_raw_parts(ptr, new_size))
+    }
+
+    /// Move a piece of memory into a new allocation fitting `new_layout`, copying its content
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated from this allocator with `old_layout`
+    #[cfg(any(feature = "global-alloc", feature = "allocator-api"))]
+    unsafe fn relocate(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Option<NonNull<[u8]>> {
+        let block = self.get_memory(new_layout)?;
+        core::ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            block.as_ptr() as *mut u8,
+            old_layout.size().min(new_layout.size()),
+        );
+        self.return_memory(ptr, old_layout);
+
+        Some(block)
+    }
+
+    /// Choose which free block of an order is handed out first, blocks already free keep their place
+    ///
+    /// Free blocks are reused last in first out by default.
+    pub fn set_reuse_policy(&self, policy: ReusePolicy) {
+        self.free_list.lock().set_policy(policy);
+    }
+
+    /// Set a callback invoked with the failing layout when an allocation through [`GlobalAlloc`] fails,
+    /// right before null is returned
+    #[cfg(feature = "global-alloc")]
+    pub fn set_alloc_error_hook(&self, hook: Option<fn(Layout)>) {
+        *self.alloc_error_hook.lock() = hook;
+    }
+
+    /// Call `visit` with the address and the size of every free block, across all orders
+    ///
+    /// `visit` runs with the heap locked, so it must not allocate from nor give memory back to this allocator,
+    /// that would spin forever. When this allocator is the `#[global_allocator]`, this rules out anything
+    /// allocating on the heap, such as pushing into a `Vec`.
+    pub fn free_regions(&self, mut visit: impl FnMut(usize, usize)) {
+        let free_list = self.free_list.lock();
+        for (order, list) in free_list.iter().enumerate() {
+            for node in list.iter().skip(1) {
+                visit(node as usize, MIN_BLOCK_SIZE << order);
+            }
+        }
+    }
+
+    /// Allocation statistics, read without locking the heap
+    ///
+    /// Counters are read one by one, so a snapshot taken while another thread allocates may mix values
+    /// from before and after that allocation, e.g. `used` may briefly exceed `peak`.
+    pub fn stats(&self) -> AllocStats {
+        AllocStats {
+            used: self.used_bytes.load(Ordering::Relaxed),
+            peak: self.peak_bytes.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Bitmask of the orders having free blocks, bit `n` is set if a block of order `n` is free
+    ///
+    /// `available_orders() >> n != 0` tells whether a block of order `n` can be allocated.
+    pub fn available_orders(&self) -> usize {
+        self.free_list
+            .lock()
+            .iter()
+            .enumerate()
+            .filter(|(_, list)| !list.is_tail())
+            .fold(0, |mask, (order, _)| mask | 1 << order)
+    }
+
+    /// Number of free blocks at each order, element `n` counts the blocks of order `n`
+    pub fn free_block_counts(&self) -> [usize; ORDERS] {
+        self.free_list.lock().counts()
+    }
+
+    /// Total size of the free blocks in the heap
+    pub fn free_bytes(&self) -> usize {
+        self.free_block_counts()
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count * (MIN_BLOCK_SIZE << order))
+            .sum()
+    }
+
+    /// Total size of the blocks currently allocated
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Total size of the memory added to the heap, free or allocated
+    ///
+    /// While no other thread uses the heap, this equals `free_bytes() + used_bytes()`.
+    pub fn total_capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Verify the consistency of the heap, for tests and fuzzing
+    ///
+    /// Every free block must be counted, aligned to its size, within an added pool, apart from any other free block,
+    /// and merged with its buddy if both are free.
+    ///
+    /// # Errors
+    /// Return the first [`InvariantViolation`] found, or [`InvariantViolation::UntrackedPools`] without checking
+    /// anything if some pools were added past `MAX_POOLS`.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let free_list = self.free_list.lock();
+        let pools = self.pools.lock();
+        if self.untracked_pools.load(Ordering::Relaxed) {
+            return Err(InvariantViolation::UntrackedPools);
+        }
+        let counts = free_list.counts();
+
+        // Walks are bounded by the counts, so that a corrupted list looping on itself is still caught
+        let blocks = |order: usize| {
+            free_list[order]
+                .iter()
+                .skip(1)
+                .take(counts[order] + 1)
+                .map(|node| node as usize)
+        };
+        // Following `prev` pointers back to the head must go through the same blocks
+        let linked_back =
+            |order: usize| free_list[order].iter_rev().take(counts[order] + 2).count() == counts[order] + 1;
+        if let Some(order) = (0..ORDERS).find(|&order| blocks(order).count() != counts[order] || !linked_back(order)) {
+            return Err(InvariantViolation::CountMismatch { order });
+        }
+
+        for order in 0..ORDERS {
+            let size = MIN_BLOCK_SIZE << order;
+            for (position, addr) in blocks(order).enumerate() {
+                if addr & (size - 1) != 0 {
+                    return Err(InvariantViolation::Misaligned { addr, order });
+                }
+                if !pools.iter().any(|pool| pool.start <= addr && addr + size <= pool.end) {
+                    return Err(InvariantViolation::OutsidePools { addr, order });
                 }
 
-                (*node).pop();
-                has_buddy = true;
-                break;
+                let overlaps = (0..ORDERS).any(|other_order| {
+                    let other_size = MIN_BLOCK_SIZE << other_order;
+                    blocks(other_order).enumerate().any(|(other_position, other)| {
+                        (other_order, other_position) != (order, position)
+                            && other < addr + size
+                            && addr < other + other_size
+                    })
+                });
+                if overlaps {
+                    return Err(InvariantViolation::Overlap { addr, order });
+                }
+
+                if order + 1 < ORDERS && blocks(order).any(|other| other == addr ^ size) {
+                    return Err(InvariantViolation::Uncoalesced { addr, order });
+                }
             }
+        }
 
-            if has_buddy {
-                block = block.min(buddy);
-                index += 1;
-            } else {
+        Ok(())
+    }
+
+    /// Take a free block of order `index` out of the free lists, splitting a larger block if needed
+    ///
+    /// # Safety
+    /// Free lists must only contain valid free blocks
+    unsafe fn take_block(&self, free_list: &mut FreeLists<ORDERS>, index: usize) -> Option<NonNull<[u8]>> {
+        // Nothing large enough has ever been added or is left, no need to look
+        let highest = self.highest_nonempty_order.load(Ordering::Relaxed);
+        if index > highest {
+            return None;
+        }
+
+        // Find smallest order that is available for allocation, only a single block is taken from it
+        let block = (index..=highest)
+            .find(|&order| !free_list[order].is_tail())
+            .and_then(|order| {
+                let block = free_list.pop(order)?;
+                // Split the block if it is larger than requested, giving back its upper half at each order below
+                for j in (index..order).rev() {
+                    // SAFETY: pointer is within the larger block, its size does not overflow
+                    let buddy = (block as *mut u8).add(MIN_BLOCK_SIZE << j) as *mut BlockHeader;
+                    free_list.push(j, buddy);
+                }
+                Some(block)
+            });
+        self.refresh_highest_nonempty_order(free_list);
+
+        let size = MIN_BLOCK_SIZE << index;
+        let block = block.and_then(|ptr| NonNull::new(slice_from_raw_parts_mut(ptr as *mut _, size)))?;
+        self.record_allocation(size);
+
+        Some(block)
+    }
+
+    /// Lower the order hint to the highest order that still has a free block, `free_list` must be locked
+    #[inline]
+    fn refresh_highest_nonempty_order(&self, free_list: &FreeLists<ORDERS>) {
+        let mut highest = self.highest_nonempty_order.load(Ordering::Relaxed);
+        while highest > 0 && free_list[highest].is_tail() {
+            highest -= 1;
+        }
+        self.highest_nonempty_order.store(highest, Ordering::Relaxed);
+    }
+
+    /// Account a block of `size` bytes handed out in the statistics, `free_list` must be locked
+    #[inline]
+    fn record_allocation(&self, size: usize) {
+        let used = self.used_bytes.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak_bytes.fetch_max(used, Ordering::Relaxed);
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a block resized in place in the statistics, `free_list` must be locked
+    #[inline]
+    fn record_resize(&self, old_size: usize, new_size: usize) {
+        if new_size >= old_size {
+            let used = self.used_bytes.fetch_add(new_size - old_size, Ordering::Relaxed) + new_size - old_size;
+            self.peak_bytes.fetch_max(used, Ordering::Relaxed);
+        } else {
+            self.used_bytes.fetch_sub(old_size - new_size, Ordering::Relaxed);
+        }
+    }
+
+    /// Account a block of `size` bytes given back in the statistics, `free_list` must be locked
+    #[inline]
+    fn record_deallocation(&self, size: usize) {
+        self.used_bytes.fetch_sub(size, Ordering::Relaxed);
+        self.allocations.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Put a free block back into the free lists, merging it with its buddy as long as the buddy is free
+    ///
+    /// # Safety
+    /// `block` must point to a free block of order `index`, aligned to its size
+    unsafe fn insert_block(&self, free_list: &mut FreeLists<ORDERS>, mut block: usize, mut index: usize) {
+        while index + 1 < ORDERS {
+            let buddy = block ^ (1 << (index + BASE_ORDER));
+            if !free_list.remove(index, buddy) {
                 break;
             }
+
+            block = block.min(buddy);
+            index += 1;
+        }
+
+        free_list.push(index, block as *mut _);
+        self.highest_nonempty_order.fetch_max(index, Ordering::Relaxed);
+    }
+
+    /// Deallocate several pieces of memory, locking the heap only once
+    ///
+    /// Each block is merged with its buddy as soon as both are free,
+    /// so the heap ends up fully coalesced whatever the order of `items`.
+    /// # Safety
+    pub unsafe fn return_memory_many(&self, items: &[(NonNull<u8>, Layout)]) {
+        let mut free_list = self.free_list.lock();
+        for &(ptr, layout) in items.iter().filter(|(_, layout)| layout.size() != 0) {
+            let size = block_size(layout);
+            let index = size.trailing_zeros() as usize - BASE_ORDER;
+            self.insert_block(&mut free_list, ptr.as_ptr() as usize, index);
+            self.record_deallocation(size);
+        }
+    }
+
+    /// Allocate a piece of memory like [`BuddyAllocator::get_memory`], accounting it to the subsystem `tag`
+    ///
+    /// Fails if `MAX_TAGS` other tags already have live allocations, see [`BuddyAllocator::try_allocate_tagged`].
+    /// # Safety
+    /// The memory must be given back with [`BuddyAllocator::return_memory_tagged`] and the same `tag`.
+    /// Giving it back with [`BuddyAllocator::return_memory`] leaves it accounted to `tag` forever,
+    /// which may keep one of the `MAX_TAGS` slots taken.
+    pub unsafe fn get_memory_tagged(&self, layout: Layout, tag: u16) -> Option<NonNull<[u8]>> {
+        self.try_allocate_tagged(layout, tag).ok()
+    }
+
+    /// Allocate a piece of memory like [`BuddyAllocator::get_memory_tagged`], telling why on failure
+    ///
+    /// A zero-sized `layout` uses no block, so it is never accounted and never fails for lack of a tag slot.
+    ///
+    /// # Errors
+    /// Return [`AllocFailure::TooManyTags`] if `MAX_TAGS` other tags already have live allocations,
+    /// or the [`AllocFailure`] preventing the allocation.
+    /// # Safety
+    /// Same requirements as [`BuddyAllocator::get_memory_tagged`].
+    pub unsafe fn try_allocate_tagged(&self, layout: Layout, tag: u16) -> Result<NonNull<[u8]>, AllocFailure> {
+        if layout.size() == 0 {
+            return Ok(dangling(layout));
+        }
+        // Keep accounting in the slot of this tag, or claim an unused one
+        let slot_of = |tags: &[TagUsage; MAX_TAGS]| {
+            tags.iter()
+                .position(|usage| usage.bytes != 0 && usage.tag == tag)
+                .or_else(|| tags.iter().position(|usage| usage.bytes == 0))
+        };
+        if slot_of(&self.tags.lock()).is_none() {
+            return Err(AllocFailure::TooManyTags);
         }
 
-        free_list[index].push(block as *mut _);
+        // `tags` is not held while the heap is locked
+        let block = self.try_allocate(layout)?;
+        let mut tags = self.tags.lock();
+        let Some(slot) = slot_of(&tags) else {
+            // Other tags claimed the last slots while allocating
+            drop(tags);
+            self.return_memory(block.cast(), layout);
+            return Err(AllocFailure::TooManyTags);
+        };
+        tags[slot].tag = tag;
+        tags[slot].bytes += block.len();
+
+        Ok(block)
+    }
+
+    /// Deallocate a piece of memory allocated with [`BuddyAllocator::get_memory_tagged`]
+    /// # Safety
+    /// Like `layout`, `tag` must be the same as the one used to allocate the memory.
+    pub unsafe fn return_memory_tagged(&self, ptr: NonNull<u8>, layout: Layout, tag: u16) {
+        self.return_memory(ptr, layout);
+        if layout.size() == 0 {
+            return;
+        }
+
+        let mut tags = self.tags.lock();
+        if let Some(usage) = tags.iter_mut().find(|usage| usage.bytes != 0 && usage.tag == tag) {
+            usage.bytes -= block_size(layout);
+        }
+    }
+
+    /// Bytes currently allocated under the subsystem `tag`
+    pub fn usage_by_tag(&self, tag: u16) -> usize {
+        self.tags
+            .lock()
+            .iter()
+            .find(|usage| usage.bytes != 0 && usage.tag == tag)
+            .map_or(0, |usage| usage.bytes)
     }
 }
 
@@ -182,16 +1035,36 @@ impl<const ORDERS: usize> Default for BuddyAllocator<'_, ORDERS> {
     }
 }
 
-unsafe impl<const ORDERS: usize> Sync for BuddyAllocator<'static, ORDERS> {}
+// SAFETY: free lists are only ever touched with the lock held, and the pools outlive `'a`
+unsafe impl<const ORDERS: usize> Sync for BuddyAllocator<'_, ORDERS> {}
 
 /* -------------------------------------------------------------------------------- */
 
+#[cfg(feature = "global-alloc")]
 extern crate alloc;
+#[cfg(feature = "global-alloc")]
 use alloc::alloc::GlobalAlloc;
+#[cfg(feature = "global-alloc")]
+use core::ptr::null_mut;
 
+#[cfg(feature = "global-alloc")]
+impl<const ORDERS: usize> BuddyAllocator<'static, ORDERS> {
+    /// Invoke the allocation error hook for a `layout` that failed to be allocated, return the null pointer to hand out
+    fn alloc_failed(&self, layout: Layout) -> *mut u8 {
+        // Copy the hook out so that it is free to use the allocator
+        let hook = *self.alloc_error_hook.lock();
+        if let Some(hook) = hook {
+            hook(layout);
+        }
+        null_mut()
+    }
+}
+
+#[cfg(feature = "global-alloc")]
 unsafe impl<const ORDERS: usize> GlobalAlloc for BuddyAllocator<'static, ORDERS> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.get_memory(layout).map_or(null_mut(), |ptr| ptr.as_ptr() as *mut _)
+        self.get_memory(layout)
+            .map_or_else(|| self.alloc_failed(layout), |ptr| ptr.as_ptr() as *mut _)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -199,17 +1072,71 @@ unsafe impl<const ORDERS: usize> GlobalAlloc for BuddyAllocator<'static, ORDERS>
             self.return_memory(ptr, layout);
         }
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let Some(ptr) = NonNull::new(ptr) else {
+            return null_mut();
+        };
+
+        let block = if new_size >= layout.size() {
+            self.grow_in_place(ptr, layout, new_layout)
+        } else {
+            self.shrink_in_place(ptr, layout, new_layout)
+        };
+        block
+            .or_else(|| self.relocate(ptr, layout, new_layout))
+            .map_or_else(|| self.alloc_failed(new_layout), |block| block.as_ptr() as *mut _)
+    }
 }
 
-// use alloc::alloc::Allocator;
-// use core::alloc::AllocError;
+#[cfg(feature = "allocator-api")]
+use core::alloc::{AllocError, Allocator};
 
-// unsafe impl<const ORDERS: usize> Allocator for BuddyAllocator<'_, ORDERS> {
-//     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-//         unsafe { self.get_memory(layout).ok_or(AllocError {}) }
-//     }
+#[cfg(feature = "allocator-api")]
+unsafe impl<const ORDERS: usize> Allocator for BuddyAllocator<'_, ORDERS> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.try_allocate(layout).map_err(|_| AllocError) }
+    }
 
-//     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-//         unsafe { self.return_memory(ptr, layout) }
-//     }
-// }
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.return_memory(ptr, layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.grow_in_place(ptr, old_layout, new_layout)
+            .or_else(|| self.relocate(ptr, old_layout, new_layout))
+            .ok_or(AllocError)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.grow(ptr, old_layout, new_layout)?;
+        // Only bytes past the old allocation are new, whether the block moved or not
+        (block.as_ptr() as *mut u8)
+            .add(old_layout.size())
+            .write_bytes(0, block.len() - old_layout.size());
+
+        Ok(block)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.shrink_in_place(ptr, old_layout, new_layout)
+            .or_else(|| self.relocate(ptr, old_layout, new_layout))
+            .ok_or(AllocError)
+    }
+}
@@ -1,6 +1,8 @@
 use super::*;
 use core::mem::{align_of, size_of_val};
 
+extern crate std;
+
 // Ensure that a byte array is align to this size, which enables it to be added to the heap as a full block
 #[repr(align(256))]
 #[derive(Clone, Copy)]
@@ -9,6 +11,53 @@ struct Aligned(u8);
 
 const ORDERS: usize = align_of::<Aligned>().trailing_zeros() as usize - BASE_ORDER + 1;
 
+#[test]
+fn test_order_helpers() {
+    // Not a power of two
+    assert_eq!(order_from_max_block_size(MIN_BLOCK_SIZE * 3), None);
+    // Smaller than a single block
+    assert_eq!(order_from_max_block_size(MIN_BLOCK_SIZE >> 1), None);
+    assert_eq!(order_from_max_block_size(0), None);
+
+    assert_eq!(order_from_max_block_size(MIN_BLOCK_SIZE), Some(1));
+    assert_eq!(max_block_size_from_order(1), MIN_BLOCK_SIZE);
+    for orders in 1..16 {
+        let max_block_size = max_block_size_from_order(orders);
+        assert_eq!(order_from_max_block_size(max_block_size), Some(orders));
+    }
+    assert_eq!(
+        order_from_max_block_size(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE),
+        Some(ORDERS)
+    );
+}
+
+#[test]
+fn test_recommended_orders() {
+    assert_eq!(recommended_orders(0), None);
+    assert_eq!(recommended_orders(MIN_BLOCK_SIZE - 1), None);
+    assert_eq!(recommended_orders(MIN_BLOCK_SIZE), Some(1));
+    assert_eq!(recommended_orders(MIN_BLOCK_SIZE * 3), Some(2));
+    assert_eq!(recommended_orders(MIN_BLOCK_SIZE * 4 - 1), Some(2));
+    assert_eq!(recommended_orders(MIN_BLOCK_SIZE * 4), Some(3));
+    assert_eq!(recommended_orders(MIN_BLOCK_SIZE << 16), Some(17));
+    assert_eq!(recommended_orders(align_of::<Aligned>()), Some(ORDERS));
+    assert_eq!(recommended_orders(usize::MAX), Some(usize::BITS as usize - BASE_ORDER));
+
+    // A region of the recommended size is a single block
+    let aligned_pool = [Aligned(0)];
+    let allocator = BuddyAllocator::<{ recommended_orders(size_of::<Aligned>()).unwrap() }>::new();
+    unsafe { allocator.add_memory(aligned_pool.as_ptr() as *mut u8, size_of_val(&aligned_pool)) };
+    assert_eq!(allocator.available_orders(), 1 << (ORDERS - 1));
+}
+
+#[test]
+fn test_largest_orders() {
+    const LARGEST_ORDERS: usize = usize::BITS as usize - BASE_ORDER;
+
+    let allocator = BuddyAllocator::<LARGEST_ORDERS>::new();
+    assert_eq!(allocator.get_max_block_size(), 1 << (usize::BITS - 1));
+}
+
 #[test]
 #[allow(clippy::shadow_unrelated)]
 fn test_add_memory() {
@@ -36,6 +85,111 @@ fn test_add_memory() {
     assert_eq!(added, ALL_BLOCKS_POOL_SIZE);
 }
 
+#[test]
+fn test_add_empty_memory() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    assert_eq!(unsafe { allocator.add_memory(pool_addr, 0) }, 0);
+    assert_eq!(unsafe { allocator.add_memory(core::ptr::null_mut(), pool_size) }, 0);
+    assert_eq!(
+        unsafe { allocator.add_memory_detailed(core::ptr::null_mut(), pool_size) },
+        AddReport::default()
+    );
+
+    assert_eq!(allocator.free_block_counts(), [0; ORDERS]);
+    assert!(allocator.pools.lock().iter().all(Pool::is_unused));
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_add_memory_detailed() {
+    let aligned_pool = [Aligned(0); 2];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+
+    // Misaligned start and odd size lose bytes on both ends
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    let size = MIN_BLOCK_SIZE * 5 + 3;
+    let report = unsafe { allocator.add_memory_detailed(pool_addr.add(1), size) };
+    assert_eq!(
+        report,
+        AddReport {
+            added: MIN_BLOCK_SIZE * 4,
+            wasted_head: MIN_BLOCK_SIZE - 1,
+            // The pool ends 4 bytes past a block boundary
+            wasted_tail: 4,
+        }
+    );
+    assert_eq!(report.added + report.wasted_head + report.wasted_tail, size);
+    assert_eq!(allocator.free_bytes(), report.added);
+
+    // Too small to hold a block once aligned
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    let size = MIN_BLOCK_SIZE;
+    let report = unsafe { allocator.add_memory_detailed(pool_addr.add(1), size) };
+    assert_eq!(report.added, 0);
+    assert_eq!(report.added + report.wasted_head + report.wasted_tail, size);
+}
+
+#[test]
+fn test_with_region() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    // Moved after its free lists were filled, merging must still take buddies out of them
+    let small_layout = Layout::array::<u8>(1).unwrap();
+    let block = unsafe { allocator.get_memory(small_layout) }.unwrap();
+    let allocator = std::boxed::Box::new(allocator);
+    unsafe { allocator.return_memory(block.cast(), small_layout) };
+    assert_eq!(allocator.check_invariants(), Ok(()));
+
+    let layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == pool_addr));
+}
+
+#[test]
+fn test_add_memory_slice() {
+    // Padding of `Aligned` is not initialized, it cannot be borrowed as bytes
+    #[repr(align(256))]
+    struct AlignedBytes([u8; align_of::<Aligned>()]);
+
+    let mut aligned_pool = AlignedBytes([0; align_of::<Aligned>()]);
+    let pool = &mut aligned_pool.0;
+    let (pool_addr, pool_size) = (pool.as_mut_ptr(), pool.len());
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    assert_eq!(allocator.add_memory_slice(pool), pool_size);
+
+    let layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == pool_addr));
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_add_adjacent_memory() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let half_size = BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE / 2;
+
+    // Add both halves of a maximum block separately
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    let added = unsafe { allocator.add_memory(pool_addr.add(half_size), half_size) };
+    assert_eq!(added, half_size);
+    let added = unsafe { allocator.add_memory(pool_addr, half_size) };
+    assert_eq!(added, half_size);
+
+    let layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == pool_addr));
+}
+
 #[test]
 #[allow(clippy::shadow_unrelated)]
 fn test_memory_allocation() {
@@ -73,3 +227,1108 @@ fn test_memory_allocation() {
     // No more memory to allocate
     unsafe { assert!(allocator.get_memory(layout).is_none()) };
 }
+
+#[test]
+fn test_zero_sized_allocation() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    for align in [1, 8, 64, 4096] {
+        let layout = Layout::from_size_align(0, align).unwrap();
+        let block = unsafe { allocator.get_memory(layout) }.unwrap();
+        assert_eq!(block.len(), 0);
+        assert_eq!(block.as_ptr() as *mut u8 as usize % align, 0);
+        assert_eq!(allocator.free_bytes(), pool_size);
+
+        unsafe { allocator.return_memory(block.cast(), layout) };
+        assert_eq!(allocator.free_bytes(), pool_size);
+    }
+}
+
+#[test]
+fn test_get_memory_many() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    // More blocks than the heap holds
+    const FITTING: usize = 4;
+    let layout = Layout::array::<u8>(pool_size / FITTING).unwrap();
+    let mut blocks = [None; FITTING + 2];
+    unsafe { allocator.get_memory_many(layout, &mut blocks) };
+
+    for (i, block) in blocks[..FITTING].iter().enumerate() {
+        assert!(block.is_some_and(
+            |ptr| ptr.as_ptr() as *mut u8 == unsafe { pool_addr.add(i * layout.size()) } && ptr.len() == layout.size()
+        ));
+    }
+    assert!(blocks[FITTING..].iter().all(Option::is_none));
+    assert_eq!(allocator.free_bytes(), 0);
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_reserve_blocks() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    const COUNT: usize = 6;
+    let mut blocks = [None; COUNT + 1];
+    let granted = unsafe { allocator.reserve_blocks(0, COUNT, &mut blocks) };
+    assert_eq!(granted, COUNT);
+    // Slots past `count` are left alone
+    assert!(blocks[COUNT].is_none());
+    for (i, block) in blocks[..COUNT].iter().enumerate() {
+        let addr = block.unwrap().as_ptr() as usize;
+        assert_eq!(addr % MIN_BLOCK_SIZE, 0);
+        assert!(blocks[..i].iter().all(|other| other.unwrap().as_ptr() as usize != addr));
+    }
+    assert_eq!(allocator.free_bytes(), pool_size - COUNT * MIN_BLOCK_SIZE);
+
+    // More blocks than the heap holds
+    let mut large_blocks = [None; 3];
+    let granted = unsafe { allocator.reserve_blocks(ORDERS - 2, 3, &mut large_blocks) };
+    assert_eq!(granted, 1);
+    assert_eq!(large_blocks[0].unwrap().as_ptr() as usize % (pool_size / 2), 0);
+    assert!(large_blocks[1..].iter().all(Option::is_none));
+
+    // Beyond the free lists
+    assert_eq!(unsafe { allocator.reserve_blocks(ORDERS, 1, &mut large_blocks) }, 0);
+    assert!(large_blocks[0].is_none());
+
+    let layout = Layout::from_size_align(MIN_BLOCK_SIZE, MIN_BLOCK_SIZE).unwrap();
+    for block in blocks.iter().flatten() {
+        unsafe { allocator.return_memory(*block, layout) };
+    }
+    assert_eq!(allocator.free_bytes(), pool_size / 2);
+    assert_eq!(allocator.check_invariants(), Ok(()));
+}
+
+#[test]
+fn test_return_memory_many() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    // Every leaf of the maximum block
+    const LEAVES: usize = BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE / MIN_BLOCK_SIZE;
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let mut blocks = [None; LEAVES];
+    unsafe { allocator.get_memory_many(layout, &mut blocks) };
+    assert_eq!(allocator.free_bytes(), 0);
+
+    // Interleave the order so that buddies are not freed one after the other
+    let mut items = [(NonNull::dangling(), layout); LEAVES];
+    for (i, item) in items.iter_mut().enumerate() {
+        let block = blocks[(i * 7) % LEAVES].unwrap();
+        item.0 = block.cast();
+    }
+    unsafe { allocator.return_memory_many(&items) };
+
+    let free_list = allocator.free_list.lock();
+    assert!(free_list[..ORDERS - 1].iter().all(BlockHeader::is_tail));
+    assert_eq!(free_list[ORDERS - 1].iter().skip(1).count(), 1);
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_allocation_failure() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+    const MAX_BLOCK_SIZE: usize = BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE;
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    let over_aligned = Layout::from_size_align(1, MAX_BLOCK_SIZE * 2).unwrap();
+    let result = unsafe { allocator.try_allocate(over_aligned) };
+    assert_eq!(result, Err(AllocFailure::AlignmentTooLarge));
+
+    let over_sized = Layout::array::<u8>(MAX_BLOCK_SIZE + 1).unwrap();
+    let result = unsafe { allocator.try_allocate(over_sized) };
+    assert_eq!(result, Err(AllocFailure::SizeTooLarge));
+
+    let layout = Layout::array::<u8>(MAX_BLOCK_SIZE).unwrap();
+    let result = unsafe { allocator.try_allocate(layout) };
+    assert!(result.is_ok_and(|ptr| ptr.as_ptr() as *mut u8 == pool_addr));
+    let result = unsafe { allocator.try_allocate(layout) };
+    assert_eq!(result, Err(AllocFailure::OutOfMemory));
+}
+
+#[test]
+fn test_oversized_allocation() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+    assert_eq!(allocator.max_alloc(), BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE);
+
+    let layout = Layout::array::<u8>(allocator.max_alloc() + 1).unwrap();
+    unsafe { assert!(allocator.get_memory(layout).is_none()) };
+    unsafe { assert!(allocator.get_memory_with_order(layout).is_none()) };
+    let mut blocks = [None; 2];
+    unsafe { allocator.get_memory_many(layout, &mut blocks) };
+    assert!(blocks.iter().all(Option::is_none));
+
+    // The heap is untouched
+    assert_eq!(allocator.free_bytes(), pool_size);
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_split_smallest_block() {
+    let aligned_pool = [Aligned(0); 2];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let max_size = BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE;
+    let medium_size = MIN_BLOCK_SIZE << 2;
+
+    // A max block, then a medium block that has no free buddy
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    unsafe { allocator.add_memory(pool_addr, max_size) };
+    unsafe { allocator.add_memory(pool_addr.add(max_size), medium_size) };
+
+    // Only the medium block is split
+    let layout = Layout::array::<u8>(1).unwrap();
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == unsafe { pool_addr.add(max_size) }));
+    let mut counts = [0; ORDERS];
+    counts[0] = 1;
+    counts[1] = 1;
+    counts[ORDERS - 1] = 1;
+    assert_eq!(allocator.free_block_counts(), counts);
+
+    let full_layout = Layout::array::<u8>(max_size).unwrap();
+    let result = unsafe { allocator.get_memory(full_layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == pool_addr));
+}
+
+#[test]
+fn test_reuse_policy() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+
+    for (policy, reused) in [(ReusePolicy::Lifo, 2), (ReusePolicy::Fifo, 0)] {
+        let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, MIN_BLOCK_SIZE * 4) };
+        allocator.set_reuse_policy(policy);
+        let blocks = [(); 4].map(|()| unsafe { allocator.get_memory(layout) }.unwrap().cast::<u8>());
+
+        // Neither freed block has a free buddy, so both stay in the smallest list
+        unsafe { allocator.return_memory(blocks[0], layout) };
+        unsafe { allocator.return_memory(blocks[2], layout) };
+        let result = unsafe { allocator.get_memory(layout) };
+        assert_eq!(result.map(NonNull::cast::<u8>), Some(blocks[reused]));
+        assert_eq!(allocator.check_invariants(), Ok(()));
+    }
+}
+
+#[test]
+fn test_fifo_tail() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let [a, b, c, d, e] = [0, 2, 4, 6, 8].map(|offset| unsafe { pool_addr.add(offset * MIN_BLOCK_SIZE) } as usize);
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    allocator.set_reuse_policy(ReusePolicy::Fifo);
+    let mut free_list = allocator.free_list.lock();
+    let blocks = |lists: &FreeLists<ORDERS>| lists[0].iter().skip(1).map(|node| node as usize);
+
+    for block in [a, b, c] {
+        unsafe { free_list.push(0, block as *mut _) };
+    }
+    assert!(blocks(&free_list).eq([a, b, c]));
+
+    // Taking out the last block or the first one, then appending again
+    assert!(free_list.remove(0, c));
+    unsafe { free_list.push(0, d as *mut _) };
+    assert!(blocks(&free_list).eq([a, b, d]));
+    assert!(free_list.remove(0, a));
+    unsafe { free_list.push(0, e as *mut _) };
+    assert!(blocks(&free_list).eq([b, d, e]));
+
+    // Emptied, the next block is both first and last
+    while free_list.pop(0).is_some() {}
+    for block in [a, b] {
+        unsafe { free_list.push(0, block as *mut _) };
+    }
+    assert!(blocks(&free_list).eq([a, b]));
+}
+
+#[test]
+fn test_failed_allocation_keeps_heap() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    // Only blocks smaller than the heap are left, one of each order
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+    let small_layout = Layout::array::<u8>(1).unwrap();
+    unsafe { allocator.get_memory(small_layout) }.unwrap();
+    let counts = allocator.free_block_counts();
+
+    let full_layout = Layout::array::<u8>(pool_size).unwrap();
+    let over_aligned = Layout::from_size_align(1, pool_size * 2).unwrap();
+    let oversized = Layout::array::<u8>(pool_size * 2).unwrap();
+    for layout in [full_layout, over_aligned, oversized] {
+        unsafe { assert!(allocator.get_memory(layout).is_none()) };
+        assert_eq!(allocator.free_block_counts(), counts);
+    }
+    assert_eq!(allocator.check_invariants(), Ok(()));
+}
+
+#[test]
+fn test_allocation_order() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    let layout = Layout::array::<u8>(100).unwrap();
+    let (block, order) = unsafe { allocator.get_memory_with_order(layout) }.unwrap();
+    assert_eq!(MIN_BLOCK_SIZE << order, 128);
+    assert_eq!(block.len(), 128);
+
+    // No block is taken for a zero-sized layout, which could not be given back
+    let free_bytes = allocator.free_bytes();
+    let zero_sized = Layout::array::<u8>(0).unwrap();
+    assert!(unsafe { allocator.get_memory_with_order(zero_sized) }.is_none());
+    assert_eq!(allocator.free_bytes(), free_bytes);
+    assert_eq!(allocator.used_bytes(), 128);
+}
+
+#[test]
+fn test_free_regions() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+    assert_eq!(allocator.free_bytes(), pool_size);
+
+    // Leaves one free block of each order below the top one
+    let layout = Layout::array::<u8>(1).unwrap();
+    let block = unsafe { allocator.get_memory(layout) }.unwrap();
+
+    let mut regions = [(0, 0); ORDERS];
+    let mut count = 0;
+    allocator.free_regions(|addr, size| {
+        regions[count] = (addr, size);
+        count += 1;
+    });
+    assert_eq!(count, ORDERS - 1);
+    for (order, &(addr, size)) in regions[..count].iter().enumerate() {
+        assert_eq!(size, MIN_BLOCK_SIZE << order);
+        assert_eq!(addr, pool_addr as usize + size);
+    }
+
+    let total: usize = regions.iter().map(|&(_, size)| size).sum();
+    assert_eq!(total, allocator.free_bytes());
+    assert_eq!(total, pool_size - block.len());
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_merge_pools() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+    let half_size = pool_size / 2;
+    let layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+
+    let mut counts = [0; ORDERS];
+    counts[ORDERS - 1] = 1;
+
+    // Adding memory already merges buddies, nothing is left to merge
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    unsafe { allocator.add_memory(pool_addr, half_size) };
+    unsafe { allocator.add_memory(pool_addr.add(half_size), half_size) };
+    assert_eq!(allocator.free_block_counts(), counts);
+    assert_eq!(allocator.defragment(usize::MAX), 0);
+    allocator.merge_pools();
+    assert_eq!(allocator.free_block_counts(), counts);
+
+    // Buddies left apart at every order, as smallest blocks
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    {
+        let mut free_list = allocator.free_list.lock();
+        for offset in (0..pool_size).step_by(MIN_BLOCK_SIZE) {
+            unsafe { free_list.push(0, pool_addr.add(offset) as *mut _) };
+        }
+    }
+    unsafe { assert!(allocator.get_memory(layout).is_none()) };
+
+    allocator.merge_pools();
+    assert_eq!(allocator.free_block_counts(), counts);
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == pool_addr));
+}
+
+#[test]
+fn test_defragment() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+    let layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+
+    // Buddies left apart at every order, as smallest blocks
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    {
+        let mut free_list = allocator.free_list.lock();
+        for offset in (0..pool_size).step_by(MIN_BLOCK_SIZE) {
+            unsafe { free_list.push(0, pool_addr.add(offset) as *mut _) };
+        }
+    }
+    assert_eq!(allocator.defragment(0), 0);
+
+    // Every merge joins two blocks into one
+    let mut merges = 0;
+    while allocator.defragment(1) == 1 {
+        merges += 1;
+    }
+    assert_eq!(merges, pool_size / MIN_BLOCK_SIZE - 1);
+    assert_eq!(allocator.defragment(usize::MAX), 0);
+    let mut counts = [0; ORDERS];
+    counts[ORDERS - 1] = 1;
+    assert_eq!(allocator.free_block_counts(), counts);
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == pool_addr));
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_check_invariants() {
+    let aligned_pool = [Aligned(0); 2];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+    let max_size = BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE;
+    let full_layout = Layout::array::<u8>(max_size).unwrap();
+
+    // Heap with one of its two max blocks allocated
+    let corruptible = || {
+        let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+        unsafe { allocator.get_memory(full_layout) }.unwrap();
+        assert_eq!(allocator.check_invariants(), Ok(()));
+        allocator
+    };
+    // The max block left free is the same in every such heap
+    let free = unsafe { corruptible().get_memory(full_layout) }.unwrap().as_ptr() as *mut u8;
+    let used = if free == pool_addr {
+        pool_addr.wrapping_add(max_size)
+    } else {
+        pool_addr
+    };
+    let corrupt = |allocator: &BuddyAllocator<ORDERS>, order: usize, addr: *mut u8| {
+        unsafe { allocator.free_list.lock().push(order, addr as *mut _) };
+    };
+
+    // Regular operations keep the heap consistent
+    let allocator = corruptible();
+    let layout = Layout::array::<u8>(1).unwrap();
+    let block = unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(allocator.check_invariants(), Ok(()));
+    unsafe { allocator.return_memory(block.cast(), layout) };
+    assert_eq!(allocator.check_invariants(), Ok(()));
+
+    let allocator = corruptible();
+    corrupt(&allocator, 1, used.wrapping_add(MIN_BLOCK_SIZE));
+    assert_eq!(
+        allocator.check_invariants(),
+        Err(InvariantViolation::Misaligned {
+            addr: used as usize + MIN_BLOCK_SIZE,
+            order: 1
+        })
+    );
+
+    let outside = [Aligned(0)];
+    let allocator = corruptible();
+    corrupt(&allocator, 0, outside.as_ptr() as *mut u8);
+    assert_eq!(
+        allocator.check_invariants(),
+        Err(InvariantViolation::OutsidePools {
+            addr: outside.as_ptr() as usize,
+            order: 0
+        })
+    );
+
+    // Within the free max block
+    let allocator = corruptible();
+    corrupt(&allocator, 0, free);
+    assert_eq!(
+        allocator.check_invariants(),
+        Err(InvariantViolation::Overlap {
+            addr: free as usize,
+            order: 0
+        })
+    );
+
+    // The same block in two lists
+    let allocator = corruptible();
+    corrupt(&allocator, 0, used);
+    corrupt(&allocator, 1, used);
+    assert_eq!(
+        allocator.check_invariants(),
+        Err(InvariantViolation::Overlap {
+            addr: used as usize,
+            order: 0
+        })
+    );
+
+    // Both halves of the allocated max block
+    let allocator = corruptible();
+    corrupt(&allocator, ORDERS - 2, used);
+    corrupt(&allocator, ORDERS - 2, used.wrapping_add(max_size / 2));
+    assert!(matches!(
+        allocator.check_invariants(),
+        Err(InvariantViolation::Uncoalesced { order, .. }) if order == ORDERS - 2
+    ));
+
+    // The same block twice in a list, which links it to itself
+    let allocator = corruptible();
+    corrupt(&allocator, 0, used);
+    corrupt(&allocator, 0, used);
+    assert_eq!(
+        allocator.check_invariants(),
+        Err(InvariantViolation::CountMismatch { order: 0 })
+    );
+}
+
+#[test]
+fn test_clear() {
+    let aligned_pool = [Aligned(0); 2];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+    let half_size = pool_size / 2;
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    unsafe { allocator.add_memory(pool_addr, half_size - MIN_BLOCK_SIZE) };
+    unsafe { allocator.add_memory(pool_addr.add(half_size), half_size) };
+    let total = allocator.free_bytes();
+    assert_eq!(total, pool_size - MIN_BLOCK_SIZE);
+
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE * 3).unwrap();
+    while unsafe { allocator.get_memory(layout) }.is_some() {}
+    assert!(allocator.free_bytes() < total);
+
+    unsafe { allocator.clear() }.unwrap();
+    assert_eq!(allocator.free_bytes(), total);
+
+    // The pool that was added whole is a single block again
+    let full_layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+    let result = unsafe { allocator.get_memory(full_layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == unsafe { pool_addr.add(half_size) }));
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_pool_limit() {
+    // Room for every pool and the gaps between them
+    let aligned_pool = [Aligned(0); MAX_POOLS * MIN_BLOCK_SIZE * 2 / align_of::<Aligned>()];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    // Separate pools, with a gap between each
+    for i in 0..MAX_POOLS {
+        let added = unsafe { allocator.add_memory(pool_addr.add(i * MIN_BLOCK_SIZE * 2), MIN_BLOCK_SIZE) };
+        assert_eq!(added, MIN_BLOCK_SIZE);
+    }
+    // A pool adjacent to a single existing one extends it
+    let last_end = (MAX_POOLS * 2 - 1) * MIN_BLOCK_SIZE;
+    let added = unsafe { allocator.add_memory(pool_addr.add(last_end), MIN_BLOCK_SIZE) };
+    assert_eq!(added, MIN_BLOCK_SIZE);
+    assert_eq!(allocator.check_invariants(), Ok(()));
+
+    // No space left to record another one, its memory is still added
+    let pool = [Aligned(0); 1];
+    let pool_size = size_of_val(&pool);
+    let added = unsafe { allocator.add_memory(pool.as_ptr() as *mut u8, pool_size) };
+    assert_eq!(added, pool_size);
+    assert_eq!(allocator.total_capacity(), (MAX_POOLS + 1) * MIN_BLOCK_SIZE + pool_size);
+    let layout = Layout::array::<u8>(pool_size).unwrap();
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.as_ptr() as *mut u8 == pool.as_ptr() as *mut u8));
+
+    // Operations needing every range refuse to run
+    assert_eq!(allocator.check_invariants(), Err(InvariantViolation::UntrackedPools));
+    let free_bytes = allocator.free_bytes();
+    assert_eq!(unsafe { allocator.clear() }, Err(UntrackedPools));
+    assert_eq!(allocator.free_bytes(), free_bytes);
+}
+
+#[test]
+fn test_fuse_pools() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+    let quarter_size = pool_size / 4;
+
+    // The last pool fills the gap between the first two, blocks merge across both boundaries
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    unsafe { allocator.add_memory(pool_addr, quarter_size) };
+    unsafe { allocator.add_memory(pool_addr.add(quarter_size * 2), quarter_size * 2) };
+    unsafe { allocator.add_memory(pool_addr.add(quarter_size), quarter_size) };
+    let mut counts = [0; ORDERS];
+    counts[ORDERS - 1] = 1;
+    assert_eq!(allocator.free_block_counts(), counts);
+    assert_eq!(allocator.check_invariants(), Ok(()));
+
+    // A single slot is left holding the whole range
+    let pools = allocator.pools.lock();
+    assert_eq!(pools.iter().filter(|pool| !pool.is_unused()).count(), 1);
+    assert!(pools
+        .iter()
+        .any(|pool| pool.start == pool_addr as usize && pool.end == pool_addr as usize + pool_size));
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_available_orders() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    assert_eq!(allocator.available_orders(), 0);
+
+    unsafe { allocator.add_memory(pool_addr, pool_size) };
+    assert_eq!(allocator.available_orders(), 1 << (ORDERS - 1));
+
+    // Splitting leaves one block of every lower order
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(allocator.available_orders(), (1 << (ORDERS - 1)) - 1);
+
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE << 1).unwrap();
+    unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(allocator.available_orders(), (1 << (ORDERS - 1)) - 1 - (1 << 1));
+    assert_eq!(allocator.available_orders() >> (ORDERS - 1), 0);
+}
+
+/// Check that the count of every order matches the number of blocks actually in its list, then return the counts
+fn synced_counts<const ORDERS: usize>(allocator: &BuddyAllocator<ORDERS>) -> [usize; ORDERS] {
+    let free_list = allocator.free_list.lock();
+    for (list, &count) in free_list.iter().zip(free_list.counts().iter()) {
+        assert_eq!(list.iter().skip(1).count(), count);
+    }
+    free_list.counts()
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_free_block_counts() {
+    let aligned_pool = [Aligned(0); 2];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    // Two top blocks, a top block split into one block of every lower order, then without its smallest blocks
+    let mut whole = [0; ORDERS];
+    whole[ORDERS - 1] = 2;
+    let split = [1; ORDERS];
+    let mut split_above_0 = split;
+    split_above_0[0] = 0;
+    let mut split_above_1 = split_above_0;
+    split_above_1[1] = 0;
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    assert_eq!(synced_counts(&allocator), [0; ORDERS]);
+    unsafe { allocator.add_memory(pool_addr, pool_size) };
+    assert_eq!(synced_counts(&allocator), whole);
+    assert_eq!(allocator.free_block_counts(), whole);
+
+    // Splitting a top block leaves one block of every lower order
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let first = unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(synced_counts(&allocator), split);
+    let second = unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(synced_counts(&allocator), split_above_0);
+
+    // Merging climbs back up only once both buddies are free
+    unsafe { allocator.return_memory(first.cast(), layout) };
+    assert_eq!(synced_counts(&allocator), split);
+    unsafe { allocator.return_memory(second.cast(), layout) };
+    assert_eq!(synced_counts(&allocator), whole);
+
+    // Resizing in place takes and gives back the buddies
+    let block = unsafe { allocator.get_memory(layout) }.unwrap();
+    let large_layout = Layout::array::<u8>(MIN_BLOCK_SIZE << 2).unwrap();
+    unsafe { allocator.grow_in_place(block.cast(), layout, large_layout) }.unwrap();
+    assert_eq!(synced_counts(&allocator), split_above_1);
+    unsafe { allocator.shrink_in_place(block.cast(), large_layout, layout) }.unwrap();
+    assert_eq!(synced_counts(&allocator), split);
+    unsafe { allocator.return_memory(block.cast(), layout) };
+    assert_eq!(synced_counts(&allocator), whole);
+
+    // Batched operations keep them in sync as well
+    let mut blocks = [None; 4];
+    unsafe { allocator.get_memory_many(layout, &mut blocks) };
+    assert_eq!(synced_counts(&allocator), split_above_1);
+    let items = blocks.map(|block| (block.unwrap().cast(), layout));
+    unsafe { allocator.return_memory_many(&items) };
+    assert_eq!(synced_counts(&allocator), whole);
+    assert_eq!(allocator.free_bytes(), pool_size);
+
+    unsafe { allocator.clear() }.unwrap();
+    assert_eq!(synced_counts(&allocator), whole);
+}
+
+#[test]
+fn test_total_capacity() {
+    let aligned_pool = [Aligned(0); 4];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool[0]);
+
+    // A full pool, and an unaligned one apart from it
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    let added = unsafe { allocator.add_memory(pool_addr, pool_size) }
+        + unsafe { allocator.add_memory(pool_addr.add(pool_size * 2 + 1), pool_size + 8) };
+    assert_eq!(allocator.total_capacity(), added);
+    assert_eq!(allocator.free_bytes(), added);
+    assert_eq!(allocator.used_bytes(), 0);
+
+    let small_layout = Layout::array::<u8>(1).unwrap();
+    let large_layout = Layout::array::<u8>(MIN_BLOCK_SIZE * 3).unwrap();
+    let small = unsafe { allocator.get_memory(small_layout) }.unwrap();
+    let large = unsafe { allocator.get_memory(large_layout) }.unwrap();
+    assert_eq!(allocator.used_bytes(), MIN_BLOCK_SIZE * 5);
+    assert_eq!(allocator.total_capacity(), added);
+    assert_eq!(allocator.free_bytes() + allocator.used_bytes(), added);
+
+    unsafe { allocator.return_memory(small.cast(), small_layout) };
+    unsafe { allocator.return_memory(large.cast(), large_layout) };
+    assert_eq!(allocator.used_bytes(), 0);
+    assert_eq!(allocator.free_bytes(), added);
+
+    // Clearing the heap keeps its pools
+    unsafe { allocator.clear() }.unwrap();
+    assert_eq!(allocator.total_capacity(), added);
+}
+
+#[test]
+fn test_stats() {
+    let aligned_pool = [Aligned(0); 2];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+    assert_eq!(allocator.stats(), AllocStats::default());
+
+    let mut blocks = [None; 8];
+    let (mut used, mut peak, mut allocations) = (0, 0, 0);
+    for round in 0..4 {
+        // Allocate blocks of varying sizes, then free every other one
+        for (i, slot) in blocks.iter_mut().enumerate().filter(|(_, slot)| slot.is_none()) {
+            let layout = Layout::array::<u8>(MIN_BLOCK_SIZE << ((i + round) % 3)).unwrap();
+            if let Some(block) = unsafe { allocator.get_memory(layout) } {
+                used += block.len();
+                allocations += 1;
+                *slot = Some((block, layout));
+            }
+        }
+        peak = peak.max(used);
+        assert_eq!(
+            allocator.stats(),
+            AllocStats {
+                used,
+                peak,
+                allocations
+            }
+        );
+
+        for slot in blocks.iter_mut().skip(round % 2).step_by(2) {
+            if let Some((block, layout)) = slot.take() {
+                unsafe { allocator.return_memory(block.cast(), layout) };
+                used -= block.len();
+                allocations -= 1;
+            }
+        }
+        assert_eq!(
+            allocator.stats(),
+            AllocStats {
+                used,
+                peak,
+                allocations
+            }
+        );
+    }
+
+    assert_eq!(allocator.stats().used, pool_size - allocator.free_bytes());
+}
+
+#[test]
+fn test_tagged_allocation() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    unsafe { allocator.add_memory(pool_addr, pool_size) };
+
+    const TAG_1: u16 = 1;
+    const TAG_2: u16 = 2;
+    let small = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let large = Layout::array::<u8>(MIN_BLOCK_SIZE * 4).unwrap();
+
+    let block_1 = unsafe { allocator.get_memory_tagged(small, TAG_1) }.unwrap();
+    let block_2 = unsafe { allocator.get_memory_tagged(large, TAG_1) }.unwrap();
+    let block_3 = unsafe { allocator.get_memory_tagged(large, TAG_2) }.unwrap();
+    assert_eq!(allocator.usage_by_tag(TAG_1), MIN_BLOCK_SIZE * 5);
+    assert_eq!(allocator.usage_by_tag(TAG_2), MIN_BLOCK_SIZE * 4);
+
+    unsafe { allocator.return_memory_tagged(block_2.cast(), large, TAG_1) };
+    assert_eq!(allocator.usage_by_tag(TAG_1), MIN_BLOCK_SIZE);
+    assert_eq!(allocator.usage_by_tag(TAG_2), MIN_BLOCK_SIZE * 4);
+
+    unsafe { allocator.return_memory_tagged(block_1.cast(), small, TAG_1) };
+    unsafe { allocator.return_memory_tagged(block_3.cast(), large, TAG_2) };
+    assert_eq!(allocator.usage_by_tag(TAG_1), 0);
+    assert_eq!(allocator.usage_by_tag(TAG_2), 0);
+}
+
+#[test]
+fn test_too_many_tags() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let zero_sized = Layout::array::<u8>(0).unwrap();
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+    for tag in 0..MAX_TAGS as u16 {
+        unsafe { allocator.try_allocate_tagged(layout, tag) }.unwrap();
+    }
+
+    // Told apart from running out of memory, and the heap is left untouched
+    let free_bytes = allocator.free_bytes();
+    let extra_tag = MAX_TAGS as u16;
+    let result = unsafe { allocator.try_allocate_tagged(layout, extra_tag) };
+    assert_eq!(result, Err(AllocFailure::TooManyTags));
+    assert!(unsafe { allocator.get_memory_tagged(layout, extra_tag) }.is_none());
+    assert_eq!(allocator.free_bytes(), free_bytes);
+
+    // Tags already having a slot and zero-sized layouts still succeed
+    assert!(unsafe { allocator.get_memory_tagged(layout, 0) }.is_some());
+    assert!(unsafe { allocator.get_memory_tagged(zero_sized, extra_tag) }.is_some());
+    assert_eq!(allocator.usage_by_tag(0), MIN_BLOCK_SIZE * 2);
+    assert_eq!(allocator.usage_by_tag(extra_tag), 0);
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_highest_nonempty_order() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    let highest = || allocator.highest_nonempty_order.load(Ordering::Relaxed);
+    assert_eq!(highest(), 0);
+
+    // Only a block of order 2 is ever added
+    let added = unsafe { allocator.add_memory(pool_addr, MIN_BLOCK_SIZE << 2) };
+    assert_eq!(added, MIN_BLOCK_SIZE << 2);
+    assert_eq!(highest(), 2);
+
+    // Larger than any added block
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE << 3).unwrap();
+    unsafe { assert!(allocator.get_memory(layout).is_none()) };
+    assert_eq!(highest(), 2);
+
+    // Splitting the only block leaves order 1 as the highest
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let block = unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(highest(), 1);
+
+    // Coalescing restores the block
+    unsafe { allocator.return_memory(block.cast(), layout) };
+    assert_eq!(highest(), 2);
+
+    // Taking the whole block empties the heap
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE << 2).unwrap();
+    let block = unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(highest(), 0);
+    assert!(allocator.free_list.lock()[0].is_tail());
+    unsafe { allocator.return_memory(block.cast(), layout) };
+    assert_eq!(highest(), 2);
+}
+
+#[test]
+fn test_alignment_above_size() {
+    let aligned_pool = [Aligned(0); 4];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+    // Leave only blocks that are not aligned to their pool
+    let small_layout = Layout::array::<u8>(1).unwrap();
+    unsafe { allocator.get_memory(small_layout) }.unwrap();
+
+    for (size, align) in [(8, 64), (1, 256)] {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let block = unsafe { allocator.get_memory(layout) }.unwrap();
+        assert_eq!(block.as_ptr() as *mut u8 as usize % align, 0);
+        assert!(block.len() >= align);
+    }
+    assert_eq!(allocator.check_invariants(), Ok(()));
+}
+
+#[test]
+fn test_full_block_usable_size() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    unsafe { allocator.add_memory(pool_addr, pool_size) };
+
+    let layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+    let mut block = unsafe { allocator.get_memory(layout) }.unwrap();
+    assert_eq!(block.len(), BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE);
+
+    // Every byte, including where the header was stored, belongs to the caller
+    let bytes = unsafe { block.as_mut() };
+    bytes.fill(0xA5);
+    assert!(bytes.iter().all(|&byte| byte == 0xA5));
+
+    unsafe { allocator.return_memory(block.cast(), layout) };
+    let result = unsafe { allocator.get_memory(layout) };
+    assert!(result.is_some_and(|ptr| ptr.len() == BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE));
+}
+
+#[test]
+fn test_scoped_threads() {
+    let aligned_pool = [Aligned(0); 4];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = BuddyAllocator::<ORDERS>::new();
+    unsafe { allocator.add_memory(pool_addr, pool_size) };
+
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    std::thread::scope(|scope| {
+        for thread in 0..4_u8 {
+            let allocator = &allocator;
+            scope.spawn(move || {
+                for _ in 0..1000 {
+                    let mut block = unsafe { allocator.get_memory(layout) }.unwrap();
+                    unsafe { block.as_mut() }.fill(thread);
+                    assert!(unsafe { block.as_ref() }.iter().all(|&byte| byte == thread));
+                    unsafe { allocator.return_memory(block.cast(), layout) };
+                }
+            });
+        }
+    });
+
+    // Everything has been coalesced back
+    let full_layout = Layout::array::<u8>(BuddyAllocator::<ORDERS>::MAX_BLOCK_SIZE).unwrap();
+    for _ in 0..aligned_pool.len() {
+        unsafe { assert!(allocator.get_memory(full_layout).is_some()) };
+    }
+}
+
+#[test]
+#[cfg(feature = "global-alloc")]
+fn test_alloc_error_hook() {
+    use core::sync::atomic::AtomicUsize;
+
+    /// Size of the last layout that failed to be allocated
+    static FAILED_SIZE: AtomicUsize = AtomicUsize::new(0);
+    fn hook(layout: Layout) {
+        FAILED_SIZE.store(layout.size(), Ordering::Relaxed);
+    }
+
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, MIN_BLOCK_SIZE * 2) };
+    allocator.set_alloc_error_hook(Some(hook));
+
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let blocks = [(); 2].map(|()| unsafe { allocator.alloc(layout) });
+    assert!(blocks.iter().all(|block| !block.is_null()));
+    assert_eq!(FAILED_SIZE.load(Ordering::Relaxed), 0);
+
+    let failing = Layout::array::<u8>(3).unwrap();
+    assert!(unsafe { allocator.alloc(failing) }.is_null());
+    assert_eq!(FAILED_SIZE.load(Ordering::Relaxed), failing.size());
+
+    // Neither growing in place nor moving is possible, the hook is told about the new size
+    let new_size = MIN_BLOCK_SIZE * 2;
+    assert!(unsafe { allocator.realloc(blocks[0], layout, new_size) }.is_null());
+    assert_eq!(FAILED_SIZE.load(Ordering::Relaxed), new_size);
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+fn test_resize_in_place() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    let small = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let large = Layout::array::<u8>(MIN_BLOCK_SIZE * 4).unwrap();
+    let first = unsafe { allocator.get_memory(small) }.unwrap().cast::<u8>();
+    assert_eq!(first.as_ptr(), pool_addr);
+
+    // The following blocks are free, growing takes them over
+    let result = unsafe { allocator.grow_in_place(first, small, large) };
+    assert!(result.is_some_and(|ptr| ptr.cast() == first && ptr.len() == large.size()));
+    assert_eq!(allocator.free_bytes(), pool_size - large.size());
+    assert_eq!(allocator.stats().used, large.size());
+
+    // Shrinking gives them back
+    let result = unsafe { allocator.shrink_in_place(first, large, small) };
+    assert!(result.is_some_and(|ptr| ptr.cast() == first && ptr.len() == small.size()));
+    assert_eq!(allocator.free_bytes(), pool_size - small.size());
+    assert_eq!(allocator.stats().used, small.size());
+
+    // The block right after is in use now, growing must fail and leave the heap untouched
+    let second = unsafe { allocator.get_memory(small) }.unwrap().cast::<u8>();
+    assert_eq!(second.as_ptr(), unsafe { pool_addr.add(MIN_BLOCK_SIZE) });
+    let free = allocator.free_bytes();
+    unsafe { assert!(allocator.grow_in_place(first, small, large).is_none()) };
+    // Not the start of a larger block
+    unsafe { assert!(allocator.grow_in_place(second, small, large).is_none()) };
+    assert_eq!(allocator.free_bytes(), free);
+}
+
+#[test]
+#[cfg(feature = "global-alloc")]
+fn test_realloc() {
+    let aligned_pool = [Aligned(0)];
+    let pool_addr = aligned_pool.as_ptr() as *mut u8;
+    let pool_size = size_of_val(&aligned_pool);
+
+    let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+    let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    unsafe { first.write_bytes(0xA5, MIN_BLOCK_SIZE) };
+
+    // Grows in place
+    let grown = unsafe { allocator.realloc(first, layout, MIN_BLOCK_SIZE * 2) };
+    assert_eq!(grown, first);
+
+    // Moves, as the next block is in use
+    let grown_layout = Layout::array::<u8>(MIN_BLOCK_SIZE * 2).unwrap();
+    let second = unsafe { allocator.alloc(layout) };
+    assert_eq!(second, unsafe { pool_addr.add(MIN_BLOCK_SIZE * 2) });
+    let moved = unsafe { allocator.realloc(first, grown_layout, MIN_BLOCK_SIZE * 4) };
+    assert!(!moved.is_null() && moved != first);
+    let content = unsafe { core::slice::from_raw_parts(moved, MIN_BLOCK_SIZE) };
+    assert!(content.iter().all(|&byte| byte == 0xA5));
+
+    // Shrinks in place
+    let moved_layout = Layout::array::<u8>(MIN_BLOCK_SIZE * 4).unwrap();
+    let shrunk = unsafe { allocator.realloc(moved, moved_layout, 1) };
+    assert_eq!(shrunk, moved);
+    assert_eq!(allocator.stats().used, MIN_BLOCK_SIZE * 2);
+}
+
+#[cfg(feature = "allocator-api")]
+mod allocator_api {
+    use super::*;
+    use core::alloc::Allocator;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_vec_grow_shrink() {
+        let aligned_pool = [Aligned(0)];
+        let pool_addr = aligned_pool.as_ptr() as *mut u8;
+        let pool_size = size_of_val(&aligned_pool);
+
+        let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+        let mut vec = Vec::<u8, _>::with_capacity_in(MIN_BLOCK_SIZE, &allocator);
+        assert_eq!(vec.as_ptr(), pool_addr as *const _);
+        vec.extend((0..).take(MIN_BLOCK_SIZE));
+
+        // Pushing past the capacity grows into the free buddy
+        vec.push(0);
+        assert_eq!(vec.as_ptr(), pool_addr as *const _);
+        assert!(vec.capacity() > MIN_BLOCK_SIZE);
+        assert_eq!(allocator.free_bytes(), pool_size - vec.capacity().next_power_of_two());
+
+        // Shrinking gives the buddy back
+        vec.truncate(1);
+        vec.shrink_to_fit();
+        assert_eq!(vec.as_ptr(), pool_addr as *const _);
+        assert_eq!(allocator.free_bytes(), pool_size - MIN_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_shared_by_ref() {
+        let aligned_pool = [Aligned(0)];
+        let pool_addr = aligned_pool.as_ptr() as *mut u8;
+        let pool_size = size_of_val(&aligned_pool);
+
+        let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+        // Both collections borrow the same allocator at the same time
+        let mut first = Vec::<u8, _>::with_capacity_in(MIN_BLOCK_SIZE, &allocator);
+        let mut second = Vec::<u8, _>::with_capacity_in(MIN_BLOCK_SIZE, allocator.by_ref());
+        first.extend([1_u8; MIN_BLOCK_SIZE]);
+        second.extend([2_u8; MIN_BLOCK_SIZE]);
+        assert_ne!(first.as_ptr(), second.as_ptr());
+        assert_eq!(allocator.free_bytes(), pool_size - MIN_BLOCK_SIZE * 2);
+        assert!(first.iter().all(|&byte| byte == 1));
+        assert!(second.iter().all(|&byte| byte == 2));
+
+        drop(first);
+        drop(second);
+        assert_eq!(allocator.free_bytes(), pool_size);
+    }
+
+    #[test]
+    fn test_grow_zeroed() {
+        let aligned_pool = [Aligned(0)];
+        let pool_addr = aligned_pool.as_ptr() as *mut u8;
+        let pool_size = size_of_val(&aligned_pool);
+
+        let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+        let small = Layout::array::<u8>(MIN_BLOCK_SIZE / 2).unwrap();
+        let large = Layout::array::<u8>(MIN_BLOCK_SIZE * 4).unwrap();
+        let block = allocator.allocate(small).unwrap();
+        // Including the part of the block past the layout
+        unsafe { (block.as_ptr() as *mut u8).write_bytes(0xFF, block.len()) };
+
+        let grown = unsafe { allocator.grow_zeroed(block.cast(), small, large) }.unwrap();
+        assert_eq!(grown.cast::<u8>(), block.cast::<u8>());
+        let content = unsafe { grown.as_ref() };
+        assert!(content[..small.size()].iter().all(|&byte| byte == 0xFF));
+        assert!(content[small.size()..].iter().all(|&byte| byte == 0));
+    }
+}
+
+#[cfg(not(feature = "global-alloc"))]
+mod no_global_alloc {
+    use super::*;
+
+    #[test]
+    fn test_raw_api() {
+        let aligned_pool = [Aligned(0)];
+        let pool_addr = aligned_pool.as_ptr() as *mut u8;
+        let pool_size = size_of_val(&aligned_pool);
+
+        let allocator = unsafe { BuddyAllocator::<ORDERS>::with_region(pool_addr, pool_size) };
+
+        let layout = Layout::array::<u8>(MIN_BLOCK_SIZE).unwrap();
+        let block = unsafe { allocator.get_memory(layout) }.unwrap();
+        assert_eq!(allocator.free_bytes(), pool_size - block.len());
+
+        unsafe { allocator.return_memory(block.cast(), layout) };
+        assert_eq!(allocator.free_bytes(), pool_size);
+    }
+}
@@ -3,32 +3,38 @@
 #![no_std]
 
 use core::cell::UnsafeCell;
+use core::fmt;
+use core::hint::spin_loop;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicBool, Ordering};
 
 /* -------------------------------------------------------------------------------- */
 
 /// A mutual exclusion primitive, useful for protecting shared data
-#[derive(Debug, Default)]
-pub struct Mutex<T> {
-    /// Data being protected
-    data: UnsafeCell<T>,
+///
+/// The data may be unsized, e.g. a `&Mutex<[u8; 4]>` coerces to a `&Mutex<[u8]>`.
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
     /// Lock state of this mutex
     lock: AtomicBool,
     // TODO: poisoned: AtomicBool,
+    /// Data being protected, last so that it can be unsized
+    data: UnsafeCell<T>,
 }
-unsafe impl<T: Send> Send for Mutex<T> {}
-unsafe impl<T: Sync> Sync for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for Mutex<T> {}
 impl<T> Mutex<T> {
     /// Create a new mutex in an unlocked state ready for use
     pub const fn new(data: T) -> Self {
         let data = UnsafeCell::new(data);
         let lock = AtomicBool::new(false);
-        Self { data, lock }
+        Self { lock, data }
     }
+}
 
+impl<T: ?Sized> Mutex<T> {
     /// Attempt to acquire this lock
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
         match self
             .lock
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
@@ -38,47 +44,114 @@ impl<T> Mutex<T> {
         }
     }
 
+    /// Attempt to acquire this lock, retrying up to `max_spins` times before giving up
+    pub fn try_lock_spins(&self, max_spins: usize) -> Option<MutexGuard<'_, T>> {
+        self.try_lock().or_else(|| {
+            (0..max_spins).find_map(|_| {
+                spin_loop();
+                self.try_lock()
+            })
+        })
+    }
+
+    /// Attempt to acquire this lock, spinning until it is lockable or `now` reaches `deadline`
+    ///
+    /// `now` is a caller-supplied monotonic tick source, checked between attempts.
+    pub fn lock_until(&self, now: impl Fn() -> u64, deadline: u64) -> Option<MutexGuard<'_, T>> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                break Some(guard);
+            }
+            if now() >= deadline {
+                break None;
+            }
+            spin_loop();
+        }
+    }
+
     /// Acquire this lock, blocking the current thread until it is lockable
-    pub fn spin_lock(&self) -> MutexGuard<T> {
+    pub fn spin_lock(&self) -> MutexGuard<'_, T> {
         loop {
             if let Some(guard) = self.try_lock() {
                 break guard;
             }
         }
     }
+
+    /// Acquire this lock, run `f` on the protected data, then release the lock
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.spin_lock())
+    }
+
+    /// Attempt to acquire this lock, and if it succeeds, run `f` on the protected data then release the lock
+    pub fn try_with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.try_lock().map(|mut guard| f(&mut guard))
+    }
+
+    /// Get a mutable reference to the protected data, no locking is needed as the mutex is exclusively borrowed
+    pub const fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Mutex");
+        // Never block, only show the data if it is not locked
+        match self.try_lock() {
+            Some(guard) => debug.field("data", &&*guard),
+            None => debug.field("data", &format_args!("<locked>")),
+        };
+        debug.finish()
+    }
 }
 
 /* -------------------------------------------------------------------------------- */
 
 /// An RAII implementation of a “scoped lock” of a mutex
 #[must_use]
-#[derive(Debug)]
-pub struct MutexGuard<'a, T> {
+pub struct MutexGuard<'a, T: ?Sized> {
     /// Mutex that this guard is locking
     mutex: &'a Mutex<T>,
 }
-impl<T> Deref for MutexGuard<'_, T> {
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         unsafe { &*self.mutex.data.get() }
     }
 }
-impl<T> DerefMut for MutexGuard<'_, T> {
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { &mut *self.mutex.data.get() }
     }
 }
-impl<T> Drop for MutexGuard<'_, T> {
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
     fn drop(&mut self) {
         self.mutex.lock.store(false, Ordering::Release);
     }
 }
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The guard holds the lock, going through the mutex would only show it as locked
+        fmt::Debug::fmt(&**self, f)
+    }
+}
 
 /* -------------------------------------------------------------------------------- */
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::cell::Cell;
+
+    extern crate std;
+    use std::format;
 
     #[test]
     fn test_try_lock() {
@@ -101,4 +174,111 @@ mod tests {
         let lock = mutex.try_lock();
         assert!(lock.is_some());
     }
+
+    #[test]
+    fn test_try_lock_spins() {
+        let mutex = Mutex::new(0);
+
+        let lock = mutex.try_lock();
+        assert!(lock.is_some());
+        assert!(mutex.try_lock_spins(0).is_none());
+        assert!(mutex.try_lock_spins(1000).is_none());
+        drop(lock);
+
+        // Released by another thread while spinning
+        let locked = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _lock = mutex.spin_lock();
+                locked.store(true, Ordering::Release);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            });
+            while !locked.load(Ordering::Acquire) {
+                spin_loop();
+            }
+            assert!(mutex.try_lock_spins(usize::MAX).is_some());
+        });
+    }
+
+    #[test]
+    fn test_lock_until() {
+        let mutex = Mutex::new(0);
+        let ticks = Cell::new(0);
+        let now = || {
+            let tick = ticks.get();
+            ticks.set(tick + 1);
+            tick
+        };
+
+        // Lockable, the deadline is never checked
+        let lock = mutex.lock_until(now, 0);
+        assert!(lock.is_some());
+        assert_eq!(ticks.get(), 0);
+
+        // Held, gives up once the deadline is reached
+        assert!(mutex.lock_until(now, 10).is_none());
+        assert_eq!(ticks.get(), 11);
+        drop(lock);
+
+        assert!(mutex.lock_until(now, 0).is_some());
+    }
+
+    #[test]
+    fn test_with_lock() {
+        let mutex = Mutex::new(0);
+
+        let result = mutex.with_lock(|data| {
+            *data += 1;
+            *data * 10
+        });
+        assert_eq!(result, 10);
+        assert_eq!(mutex.try_with_lock(|data| *data), Some(1));
+
+        let lock = mutex.try_lock();
+        assert!(lock.is_some());
+        assert_eq!(mutex.try_with_lock(|data| *data), None);
+
+        drop(lock);
+        assert_eq!(mutex.try_with_lock(|data| *data), Some(1));
+    }
+
+    #[test]
+    fn test_unsized() {
+        let mutex = Mutex::new([1_u8, 2, 3, 4]);
+        let bytes: &Mutex<[u8]> = &mutex;
+
+        bytes.with_lock(|data| data[1..].fill(0));
+        let lock = bytes.try_lock();
+        assert!(lock.is_some_and(|data| *data == [1, 0, 0, 0]));
+        assert_eq!(format!("{bytes:?}"), "Mutex { data: [1, 0, 0, 0] }");
+    }
+
+    #[test]
+    fn test_debug() {
+        let mutex = Mutex::new(42);
+        assert_eq!(format!("{mutex:?}"), "Mutex { data: 42 }");
+
+        let lock = mutex.try_lock().unwrap();
+        assert!(format!("{mutex:?}").contains("<locked>"));
+        assert_eq!(format!("{lock:?}"), "42");
+
+        drop(lock);
+        assert_eq!(format!("{mutex:?}"), "Mutex { data: 42 }");
+    }
+
+    #[test]
+    fn test_from() {
+        let mut number: Mutex<u32> = 7.into();
+        *number.get_mut() += 1;
+        assert_eq!(*number.get_mut(), 8);
+
+        #[derive(Debug, PartialEq)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        let mut point = Mutex::from(Point { x: 1, y: -1 });
+        point.get_mut().y = 2;
+        assert_eq!(*point.get_mut(), Point { x: 1, y: 2 });
+    }
 }